@@ -18,6 +18,7 @@ defmodule Scenic.Driver.Skia do
   import Bitwise, only: [|||: 2]
   alias Scenic.Driver
 
+  alias Scenic.Driver.Skia.Extension
   alias Scenic.Driver.Skia.Native
   alias Scenic.Assets.Static
   alias Scenic.Assets.Stream
@@ -34,11 +35,28 @@ defmodule Scenic.Driver.Skia do
     input_log: [type: :boolean, default: false]
   ]
 
+  @assets_schema [
+    shared_fonts: [type: :boolean, default: false],
+    image_cache_bytes: [type: {:or, [:pos_integer, nil]}, default: nil]
+  ]
+
+  @text_schema [
+    fallback_fonts: [type: {:list, {:or, [:atom, :string]}}, default: []],
+    system_fonts: [type: :boolean, default: false],
+    hinting: [type: {:in, [:none, :slight, :normal, :full]}, default: :normal],
+    subpixel: [type: :boolean, default: false],
+    edging: [type: {:in, [:alias, :antialias, :subpixel_antialias]}, default: :antialias],
+    embolden: [type: :boolean, default: false]
+  ]
+
   @opts_schema [
     backend: [type: {:or, [:atom, :string]}, default: :wayland],
     debug: [type: :boolean, default: false],
+    max_fps: [type: {:or, [:pos_integer, nil]}, default: nil],
     window: [type: :keyword_list, keys: @window_schema, default: []],
-    drm: [type: :keyword_list, keys: @drm_schema, default: []]
+    drm: [type: :keyword_list, keys: @drm_schema, default: []],
+    assets: [type: :keyword_list, keys: @assets_schema, default: []],
+    text: [type: :keyword_list, keys: @text_schema, default: []]
   ]
 
   @input_mask_key 0x01
@@ -72,6 +90,10 @@ defmodule Scenic.Driver.Skia do
     drm_card = Keyword.get(drm_opts, :card)
     drm_hw_cursor = Keyword.get(drm_opts, :hw_cursor, true)
     drm_input_log = Keyword.get(drm_opts, :input_log, false)
+    assets_opts = Keyword.get(opts, :assets, [])
+    shared_fonts = Keyword.get(assets_opts, :shared_fonts, false)
+    image_cache_bytes = Keyword.get(assets_opts, :image_cache_bytes)
+    text_opts = Keyword.get(opts, :text, [])
 
     case Native.start(
            opts[:backend],
@@ -80,10 +102,15 @@ defmodule Scenic.Driver.Skia do
            window_resizeable,
            drm_card,
            drm_hw_cursor,
-           drm_input_log
+           drm_input_log,
+           opts[:max_fps],
+           shared_fonts,
+           image_cache_bytes,
+           font_rendering(text_opts)
          ) do
       {:ok, renderer} ->
         maybe_set_input_target(renderer, self())
+        maybe_set_font_fallback(renderer, text_opts)
 
         {:ok,
          assign(driver,
@@ -102,10 +129,25 @@ defmodule Scenic.Driver.Skia do
     end
   end
 
+  defp font_rendering(text_opts) do
+    %{
+      hinting: Atom.to_string(Keyword.get(text_opts, :hinting, :normal)),
+      subpixel: Keyword.get(text_opts, :subpixel, false),
+      edging: Atom.to_string(Keyword.get(text_opts, :edging, :antialias)),
+      embolden: Keyword.get(text_opts, :embolden, false)
+    }
+  end
+
   @impl Scenic.Driver
   def reset_scene(driver) do
     Logger.debug("Scenic.Driver.Skia reset_scene")
-    _ = Native.reset_scene(driver.assigns.renderer)
+
+    driver =
+      with_update(driver, fn driver ->
+        _ = Native.reset_scene(driver.assigns.renderer)
+        driver
+      end)
+
     {:ok, driver}
   end
 
@@ -178,6 +220,12 @@ defmodule Scenic.Driver.Skia do
   def update_scene(script_ids, %{viewport: vp} = driver) do
     Logger.debug("Scenic.Driver.Skia update_scene: #{inspect(script_ids)}")
 
+    driver = with_update(driver, &submit_scene(script_ids, vp, &1))
+    driver = maybe_log_script_count(driver)
+    {:ok, driver}
+  end
+
+  defp submit_scene(script_ids, vp, driver) do
     {updates, driver} =
       Enum.reduce(script_ids, {[], driver}, fn id, {acc, driver} ->
         case ViewPort.get_script(vp, id) do
@@ -207,8 +255,21 @@ defmodule Scenic.Driver.Skia do
         end
     end
 
-    driver = maybe_log_script_count(driver)
-    {:ok, driver}
+    driver
+  end
+
+  # Scripts and assets submitted inside `fun` become visible to the renderer together,
+  # so a frame never shows a root that references a not-yet-submitted child. Updates
+  # don't nest; this process is the renderer's only writer.
+  defp with_update(driver, fun) do
+    renderer = driver.assigns.renderer
+    _ = Native.begin_update(renderer)
+
+    try do
+      fun.(driver)
+    after
+      _ = Native.commit_update(renderer)
+    end
   end
 
   defp normalize_viewport_size(nil), do: nil
@@ -227,6 +288,13 @@ defmodule Scenic.Driver.Skia do
     encode_clip_path(mode)
   end
 
+  defp serialize_op(op) when is_tuple(op) do
+    case Extension.serialize(op) do
+      {:ok, binary} -> binary
+      :error -> op
+    end
+  end
+
   defp serialize_op(other), do: other
 
   defp encode_clip_path(:intersect), do: <<0x0045::16-big, 0x00::16-big>>
@@ -239,7 +307,13 @@ defmodule Scenic.Driver.Skia do
   @impl Scenic.Driver
   def del_scripts(script_ids, driver) do
     Logger.debug("Scenic.Driver.Skia del_scripts: #{inspect(script_ids)}")
-    Enum.each(script_ids, &Native.del_script(driver.assigns.renderer, to_string(&1)))
+
+    driver =
+      with_update(driver, fn driver ->
+        Enum.each(script_ids, &Native.del_script(driver.assigns.renderer, to_string(&1)))
+        driver
+      end)
+
     {:ok, driver}
   end
 
@@ -267,13 +341,8 @@ defmodule Scenic.Driver.Skia do
 
     fonts =
       Enum.reduce(ids, fonts, fn id, fonts ->
-        with false <- Enum.member?(fonts, id),
-             {:ok, {Static.Font, _}} <- Static.meta(id),
-             {:ok, hash} <- Static.to_hash(id),
-             {:ok, bin} <- Static.load(id) do
-          _ = Native.put_font(renderer, hash, bin)
-          [id | fonts]
-        else
+        case upload_font(renderer, id) do
+          {:ok, _font_id} -> [id | List.delete(fonts, id)]
           _ -> fonts
         end
       end)
@@ -288,12 +357,12 @@ defmodule Scenic.Driver.Skia do
 
     images =
       Enum.reduce(ids, images, fn id, images ->
-        with false <- Enum.member?(images, id),
-             {:ok, {Static.Image, _}} <- Static.meta(id),
+        with {:ok, {Static.Image, _}} <- Static.meta(id),
              {:ok, hash} <- Static.to_hash(id),
+             false <- asset_loaded?(renderer, "image", hash),
              {:ok, bin} <- Static.load(id) do
           _ = Native.put_static_image(renderer, hash, bin)
-          [id | images]
+          [id | List.delete(images, id)]
         else
           _ -> images
         end
@@ -304,17 +373,29 @@ defmodule Scenic.Driver.Skia do
 
   defp ensure_streams(driver, []), do: driver
 
-  defp ensure_streams(%{assigns: %{media: media}} = driver, ids) do
+  defp ensure_streams(%{assigns: %{renderer: renderer, media: media}} = driver, ids) do
     streams = Map.get(media, :streams, [])
 
     streams =
       Enum.reduce(ids, streams, fn id, streams ->
-        with false <- Enum.member?(streams, id),
-             :ok <- Stream.subscribe(id) do
-          _ = put_stream_asset(id, driver)
-          [id | streams]
-        else
-          _ -> streams
+        cond do
+          not Enum.member?(streams, id) ->
+            case Stream.subscribe(id) do
+              :ok ->
+                _ = put_stream_asset(id, driver)
+                [id | streams]
+
+              _ ->
+                streams
+            end
+
+          # Subscribed, but the texture was evicted while no script drew it.
+          not asset_loaded?(renderer, "stream", id) ->
+            _ = put_stream_asset(id, driver)
+            streams
+
+          true ->
+            streams
         end
       end)
 
@@ -345,6 +426,26 @@ defmodule Scenic.Driver.Skia do
     end
   end
 
+  defp maybe_set_font_fallback(renderer, text_opts) do
+    fonts = Keyword.get(text_opts, :fallback_fonts, [])
+    system_fonts = Keyword.get(text_opts, :system_fonts, false)
+
+    if fonts != [] or system_fonts do
+      case set_font_fallback(renderer, fonts, system_fonts: system_fonts) do
+        :ok -> :ok
+        {:error, reason} -> Logger.warning("set_font_fallback failed: #{inspect(reason)}")
+      end
+    end
+  end
+
+  defp asset_loaded?(renderer, kind, id) do
+    case Native.asset_loaded(renderer, kind, id) do
+      {:ok, loaded} -> loaded
+      loaded when is_boolean(loaded) -> loaded
+      _ -> false
+    end
+  end
+
   defp drop_stream(id, %{assigns: %{media: media}} = driver) do
     streams = List.delete(Map.get(media, :streams, []), id)
     assign(driver, :media, Map.put(media, :streams, streams))
@@ -375,7 +476,7 @@ defmodule Scenic.Driver.Skia do
   def start(backend) when is_atom(backend) or is_binary(backend) do
     backend
     |> normalize_backend()
-    |> Native.start(nil, "Scenic Window", false, nil, true, false)
+    |> Native.start(nil, "Scenic Window", false, nil, true, false, nil, false, nil, nil)
   end
 
   @doc """
@@ -410,6 +511,246 @@ defmodule Scenic.Driver.Skia do
     |> normalize_start_result()
   end
 
+  @doc """
+  Return the script ids a script draws and the script ids that draw it.
+
+  Ids referenced by a script that were never submitted, or were deleted, still show
+  up in the returned lists, which helps track down orphaned `draw_script` references.
+
+  Accepts a renderer handle returned by `start/0` or `start/1`.
+  """
+  @spec script_dependencies(term(), String.t()) ::
+          {:ok, {[String.t()], [String.t()]}} | {:error, term()}
+  def script_dependencies(renderer, id) when is_binary(id) do
+    Native.script_dependencies(renderer, id)
+  end
+
+  @doc """
+  Remove a static image from the renderer's image cache.
+
+  Accepts a static asset id or alias. The driver uploads the image again the next time
+  a script draws it.
+
+  Accepts a renderer handle returned by `start/0` or `start/1`.
+  """
+  @spec del_static_image(term(), atom() | String.t()) :: :ok | {:error, term()}
+  def del_static_image(renderer, id) do
+    with {:ok, hash} <- Static.to_hash(id) do
+      Native.del_static_image(renderer, hash)
+      |> normalize_start_result()
+    end
+  end
+
+  @doc """
+  Remove a font from the renderer's font cache, or from the shared font pool when the
+  driver runs with `assets: [shared_fonts: true]`.
+
+  Accepts a static asset id or alias. The driver uploads the font again the next time
+  a script uses it.
+
+  Accepts a renderer handle returned by `start/0` or `start/1`.
+  """
+  @spec del_font(term(), atom() | String.t()) :: :ok | {:error, term()}
+  def del_font(renderer, id) do
+    with {:ok, hash} <- Static.to_hash(id) do
+      Native.del_font(renderer, hash)
+      |> normalize_start_result()
+    end
+  end
+
+  @doc """
+  Set the fonts used for characters missing from a text's own font.
+
+  `fonts` are static font asset ids or aliases, tried in order for each run of
+  characters the text's font has no glyphs for; they are uploaded if needed. Pass
+  `system_fonts: true` to try the system fonts after them. An empty list with
+  `system_fonts: false` turns fallback off.
+
+  Accepts a renderer handle returned by `start/0` or `start/1`.
+  """
+  @spec set_font_fallback(term(), [atom() | String.t()], keyword()) :: :ok | {:error, term()}
+  def set_font_fallback(renderer, fonts, opts \\ []) when is_list(fonts) do
+    with {:ok, hashes} <- load_fallback_fonts(renderer, fonts) do
+      Native.set_font_fallback(renderer, hashes, Keyword.get(opts, :system_fonts, false))
+      |> normalize_start_result()
+    end
+  end
+
+  defp load_fallback_fonts(renderer, fonts) do
+    fonts
+    |> Enum.reduce_while({:ok, []}, fn id, {:ok, hashes} ->
+      case upload_font(renderer, id) do
+        {:ok, hash} -> {:cont, {:ok, [hash | hashes]}}
+        error -> {:halt, error}
+      end
+    end)
+    |> case do
+      {:ok, hashes} -> {:ok, Enum.reverse(hashes)}
+      error -> error
+    end
+  end
+
+  # Uploads the font, or the face and instance selected by a `font_variant/2` id, and
+  # returns the id scripts refer to it by.
+  defp upload_font(renderer, id) do
+    with {:ok, {base, face, axes}} <- parse_font_variant(id),
+         {:ok, {Static.Font, _}} <- Static.meta(base),
+         {:ok, hash} <- Static.to_hash(base),
+         font_id = font_variant_id(hash, face, axes),
+         :ok <- load_font(renderer, base, font_id, face, axes) do
+      {:ok, font_id}
+    else
+      _ -> {:error, {:invalid_font, id}}
+    end
+  end
+
+  defp load_font(renderer, base, font_id, face, axes) do
+    if asset_loaded?(renderer, "font", font_id) do
+      :ok
+    else
+      with {:ok, bin} <- Static.load(base) do
+        Native.put_font(renderer, font_id, bin, face, axes)
+        |> normalize_start_result()
+      end
+    end
+  end
+
+  @doc """
+  Return the font id that selects one face or variable-font instance of a font.
+
+  `font` is a static font asset id or alias. Options:
+
+    * `:face` - index of the face in a TrueType/OpenType collection (`.ttc`),
+      defaults to `0`
+    * `:axes` - variation coordinates, such as `[wght: 700, wdth: 80]`
+
+  The id has the form `"<hash>#<face>@<axis>=<value>,..."`, with the parts left out
+  when they are defaults. Use it wherever a script takes a font id, for example
+  `Scenic.Script.font(ops, id)`; the driver loads that face the first time a script
+  uses it. `set_font_fallback/3` and `measure_text/4` accept these ids too.
+  """
+  @spec font_variant(atom() | String.t(), keyword()) :: {:ok, String.t()} | {:error, term()}
+  def font_variant(font, opts \\ []) do
+    face = Keyword.get(opts, :face, 0)
+    axes = Keyword.get(opts, :axes, [])
+
+    with true <- is_integer(face) and face >= 0,
+         {:ok, {Static.Font, _}} <- Static.meta(font),
+         {:ok, hash} <- Static.to_hash(font) do
+      {:ok, font_variant_id(hash, face, axes)}
+    else
+      _ -> {:error, {:invalid_font, font}}
+    end
+  end
+
+  defp font_variant_id(hash, face, axes) do
+    face = if face == 0, do: "", else: "#" <> Integer.to_string(face)
+
+    axes =
+      case Enum.sort(Enum.map(axes, fn {tag, value} -> {to_string(tag), value / 1} end)) do
+        [] -> ""
+        axes -> "@" <> Enum.map_join(axes, ",", fn {tag, value} -> "#{tag}=#{value}" end)
+      end
+
+    hash <> face <> axes
+  end
+
+  defp parse_font_variant(id) when is_binary(id) do
+    {rest, axes} =
+      case String.split(id, "@", parts: 2) do
+        [rest, axes] -> {rest, String.split(axes, ",")}
+        [rest] -> {rest, []}
+      end
+
+    with {:ok, base, face} <- parse_font_face(rest),
+         {:ok, axes} <- parse_font_axes(axes) do
+      {:ok, {base, face, axes}}
+    end
+  end
+
+  defp parse_font_variant(id), do: {:ok, {id, 0, []}}
+
+  defp parse_font_face(id) do
+    case String.split(id, "#", parts: 2) do
+      [base] ->
+        {:ok, base, 0}
+
+      [base, face] ->
+        case Integer.parse(face) do
+          {face, ""} when face >= 0 -> {:ok, base, face}
+          _ -> :error
+        end
+    end
+  end
+
+  defp parse_font_axes(axes) do
+    Enum.reduce_while(axes, {:ok, []}, fn axis, {:ok, acc} ->
+      with [tag, value] <- String.split(axis, "=", parts: 2),
+           {value, ""} <- Float.parse(value) do
+        {:cont, {:ok, acc ++ [{tag, value}]}}
+      else
+        _ -> {:halt, :error}
+      end
+    end)
+  end
+
+  @doc """
+  Measure `text` as the renderer would draw it, left aligned.
+
+  `font` is a static font asset id or alias, uploaded if needed, or `nil` for the
+  default font. Fonts are resolved exactly as for rendering, including the fallback
+  fonts, so measurements match the drawn pixels.
+
+  Returns a map with `:width` (advance of the longest line), `:bounds`
+  (`{left, top, right, bottom}` of the glyph outlines), `:ascent` (negative),
+  `:descent`, `:leading` and `:line_widths`, one per line. Lengths are in pixels,
+  relative to the start of the first line's alphabetic baseline.
+
+  Accepts a renderer handle returned by `start/0` or `start/1`.
+  """
+  @spec measure_text(term(), atom() | String.t() | nil, number(), String.t()) ::
+          {:ok, map()} | {:error, term()}
+  def measure_text(renderer, font, size, text) when is_number(size) and is_binary(text) do
+    with {:ok, font_id} <- measure_font(renderer, font) do
+      Native.measure_text(renderer, font_id, size / 1, text)
+    end
+  end
+
+  defp measure_font(_renderer, nil), do: {:ok, nil}
+  defp measure_font(renderer, font), do: upload_font(renderer, font)
+
+  @doc """
+  Return entry counts and sizes of the renderer's asset caches.
+
+  The result is a map with `:images`, `:image_bytes`, `:streams`, `:stream_bytes`,
+  `:fonts` and `:budget_bytes`, the configured `assets: [image_cache_bytes: ...]`
+  budget or `nil`.
+
+  Accepts a renderer handle returned by `start/0` or `start/1`.
+  """
+  @spec cache_stats(term()) :: {:ok, map()} | {:error, term()}
+  def cache_stats(renderer) do
+    Native.cache_stats(renderer)
+  end
+
+  @doc """
+  Register a process to be told when frames reach the screen.
+
+  After each presented frame the process receives
+  `{:frame_presented, frame_number, monotonic_ns}`, where `frame_number` counts up
+  from 1 and `monotonic_ns` is read from the OS monotonic clock (`CLOCK_MONOTONIC`).
+  Frames are reported after the buffer swap on Wayland, after the page flip completes
+  on DRM, and once the frame is readable through `get_raster_frame` on raster.
+  Pass `nil` to stop the notifications.
+
+  Accepts a renderer handle returned by `start/0` or `start/1`.
+  """
+  @spec set_frame_target(term(), pid() | nil) :: :ok | {:error, term()}
+  def set_frame_target(renderer, pid) when is_pid(pid) or is_nil(pid) do
+    Native.set_frame_target(renderer, pid)
+    |> normalize_start_result()
+  end
+
   @doc """
   Update the text rendered by the driver.
 
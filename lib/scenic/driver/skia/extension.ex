@@ -0,0 +1,351 @@
+defmodule Scenic.Driver.Skia.Extension do
+  @moduledoc """
+  Encodes driver extension ops, which reach Skia features Scenic's script format has no
+  opcode for.
+
+  Extensions use opcodes `0xF000..0xFFFF`, the low 12 bits naming the extension. Each op
+  is encoded as
+
+      <<opcode::16-big, version::16-big, byte_size(payload)::32-big, payload::binary,
+        padding to 4 bytes>>
+
+  The renderer skips extensions it doesn't know, and versions newer than the ones it
+  understands, so scripts stay drawable by older drivers.
+
+  Scripts carry the driver's own extensions as tuples, such as `{:text_draw_mode,
+  :stroke}`, which `Scenic.Driver.Skia` encodes with `serialize/1`. Other extensions can
+  be added with `put/4`.
+  """
+
+  import Bitwise, only: [|||: 2]
+
+  alias Scenic.Script
+
+  @first_opcode 0xF000
+
+  @text_draw_mode 0x001
+  @text_decoration 0x002
+  @letter_spacing 0x003
+  @text_wrap 0x004
+  @push_layer 0x005
+  @blend_mode 0x006
+  @drop_shadow 0x007
+  @blur 0x008
+  @backdrop_blur 0x009
+  @fill_gradient 0x00A
+  @stroke_gradient 0x00B
+
+  # In the renderer's wire order, which follows Skia's `SkBlendMode`.
+  @blend_modes ~w(clear src dst src_over dst_over src_in dst_in src_out dst_out src_atop
+                  dst_atop xor plus modulate screen overlay darken lighten color_dodge
+                  color_burn hard_light soft_light difference exclusion multiply hue
+                  saturation color luminosity)a
+
+  @type blend_mode ::
+          :clear
+          | :src
+          | :dst
+          | :src_over
+          | :dst_over
+          | :src_in
+          | :dst_in
+          | :src_out
+          | :dst_out
+          | :src_atop
+          | :dst_atop
+          | :xor
+          | :plus
+          | :modulate
+          | :screen
+          | :overlay
+          | :darken
+          | :lighten
+          | :color_dodge
+          | :color_burn
+          | :hard_light
+          | :soft_light
+          | :difference
+          | :exclusion
+          | :multiply
+          | :hue
+          | :saturation
+          | :color
+          | :luminosity
+
+  @type id :: 0..0x0FFF
+  @type version :: 0..0xFFFF
+
+  @doc """
+  Encodes extension `id` at payload `version`.
+  """
+  @spec encode(id(), version(), iodata()) :: binary()
+  def encode(id, version, payload)
+      when id in 0..0x0FFF and version in 0..0xFFFF do
+    payload = IO.iodata_to_binary(payload)
+    size = byte_size(payload)
+    pad = rem(4 - rem(size, 4), 4)
+
+    <<@first_opcode + id::16-big, version::16-big, size::32-big, payload::binary,
+      0::size(pad * 8)>>
+  end
+
+  @doc """
+  Adds extension `id` with `payload` to a script being built with `Scenic.Script`.
+  """
+  @spec put(Script.t(), id(), version(), iodata()) :: Script.t()
+  def put(script, id, version, payload) when is_list(script) do
+    [{:skia_extension, id, version, payload} | script]
+  end
+
+  @doc """
+  Encodes a driver extension op found in a script. Returns `:error` for ops that
+  aren't extensions, and raises `ArgumentError` for invalid arguments.
+  """
+  @spec serialize(tuple()) :: {:ok, binary()} | :error
+  def serialize({:skia_extension, id, version, payload}) do
+    {:ok, encode(id, version, payload)}
+  end
+
+  def serialize({:text_draw_mode, mode}) do
+    {:ok, encode(@text_draw_mode, 1, <<text_draw_mode(mode)::16-big>>)}
+  end
+
+  def serialize({:text_decoration, decorations}) do
+    {:ok, encode(@text_decoration, 1, <<text_decoration(decorations)::16-big>>)}
+  end
+
+  def serialize({:letter_spacing, spacing}) when is_number(spacing) do
+    {:ok, encode(@letter_spacing, 1, <<spacing::float-32-big>>)}
+  end
+
+  def serialize({:text_wrap, nil}) do
+    {:ok, encode(@text_wrap, 1, <<0::16, 0::16, 0::16, 0::16, 0::32>>)}
+  end
+
+  def serialize({:text_wrap, max_width, opts})
+      when is_number(max_width) and max_width > 0 and is_list(opts) do
+    mode = text_wrap_mode(Keyword.get(opts, :wrap, :word))
+    overflow = text_overflow(Keyword.get(opts, :overflow, :clip))
+    max_lines = Keyword.get(opts, :max_lines, 0)
+
+    unless is_integer(max_lines) and max_lines in 0..0xFFFF do
+      raise ArgumentError, "invalid text_wrap max_lines: #{inspect(max_lines)}"
+    end
+
+    payload =
+      <<mode::16-big, overflow::16-big, max_lines::16-big, 0::16, max_width::float-32-big>>
+
+    {:ok, encode(@text_wrap, 1, payload)}
+  end
+
+  def serialize({:text_wrap, max_width, _opts}) do
+    raise ArgumentError, "invalid text_wrap width: #{inspect(max_width)}"
+  end
+
+  def serialize({:push_layer, alpha}), do: serialize({:push_layer, alpha, []})
+
+  def serialize({:push_layer, alpha, opts})
+      when is_number(alpha) and alpha >= 0 and alpha <= 1 and is_list(opts) do
+    bounds =
+      case Keyword.get(opts, :bounds) do
+        nil ->
+          <<0x00::16-big, 0::16>>
+
+        {x, y, w, h} when is_number(x) and is_number(y) and is_number(w) and is_number(h) ->
+          <<0x01::16-big, 0::16, x::float-32-big, y::float-32-big, w::float-32-big,
+            h::float-32-big>>
+
+        other ->
+          raise ArgumentError, "invalid push_layer bounds: #{inspect(other)}"
+      end
+
+    {:ok, encode(@push_layer, 1, [<<alpha::float-32-big>>, bounds])}
+  end
+
+  def serialize({:push_layer, alpha, _opts}) do
+    raise ArgumentError, "invalid push_layer alpha: #{inspect(alpha)}"
+  end
+
+  def serialize({:blend_mode, mode}) do
+    case Enum.find_index(@blend_modes, &(&1 == mode)) do
+      nil -> raise ArgumentError, "invalid blend_mode: #{inspect(mode)}"
+      index -> {:ok, encode(@blend_mode, 1, <<index::16-big>>)}
+    end
+  end
+
+  def serialize({:drop_shadow, nil}), do: {:ok, encode(@drop_shadow, 1, <<>>)}
+
+  def serialize({:drop_shadow, {dx, dy}, sigma, color}) when is_number(dx) and is_number(dy) do
+    {:color_rgba, {r, g, b, a}} = Scenic.Color.to_rgba(color)
+
+    payload = [
+      <<dx::float-32-big, dy::float-32-big>>,
+      sigma(:drop_shadow, sigma),
+      <<r, g, b, a>>
+    ]
+
+    {:ok, encode(@drop_shadow, 1, payload)}
+  end
+
+  def serialize({:blur, nil}), do: {:ok, encode(@blur, 1, <<>>)}
+  def serialize({:blur, sigma}), do: {:ok, encode(@blur, 1, sigma(:blur, sigma))}
+
+  # A backdrop blur applies to the next `push_layer` only; the renderer rejects a script
+  # that draws before opening that layer.
+  def serialize({:backdrop_blur, nil}), do: {:ok, encode(@backdrop_blur, 1, <<>>)}
+
+  def serialize({:backdrop_blur, sigma}) do
+    {:ok, encode(@backdrop_blur, 1, sigma(:backdrop_blur, sigma))}
+  end
+
+  def serialize({:fill_gradient, geometry, stops}),
+    do: serialize({:fill_gradient, geometry, stops, []})
+
+  def serialize({:fill_gradient, geometry, stops, opts}) do
+    {:ok, encode(@fill_gradient, 1, gradient(:fill_gradient, geometry, stops, opts))}
+  end
+
+  def serialize({:stroke_gradient, geometry, stops}),
+    do: serialize({:stroke_gradient, geometry, stops, []})
+
+  def serialize({:stroke_gradient, geometry, stops, opts}) do
+    {:ok, encode(@stroke_gradient, 1, gradient(:stroke_gradient, geometry, stops, opts))}
+  end
+
+  def serialize(_op), do: :error
+
+  @doc """
+  Blend modes accepted by `{:blend_mode, mode}`.
+  """
+  @spec blend_modes() :: [blend_mode()]
+  def blend_modes, do: @blend_modes
+
+  defp sigma(_op, {x, y}) when is_number(x) and x >= 0 and is_number(y) and y >= 0 do
+    <<x::float-32-big, y::float-32-big>>
+  end
+
+  defp sigma(op, sigma) when is_number(sigma), do: sigma(op, {sigma, sigma})
+
+  defp sigma(op, sigma) do
+    raise ArgumentError, "invalid #{op} sigma: #{inspect(sigma)}"
+  end
+
+  defp gradient(op, geometry, stops, opts) when is_list(stops) and is_list(opts) do
+    {kind, geometry} = gradient_geometry(op, geometry)
+    tile_mode = tile_mode(op, Keyword.get(opts, :tile, :clamp))
+
+    {flags, matrix} =
+      case Keyword.get(opts, :matrix) do
+        nil ->
+          {0x00, <<>>}
+
+        {a, b, c, d, e, f} = matrix ->
+          unless Enum.all?(Tuple.to_list(matrix), &is_number/1) do
+            raise ArgumentError, "invalid #{op} matrix: #{inspect(matrix)}"
+          end
+
+          {0x01, floats([a, b, c, d, e, f])}
+
+        other ->
+          raise ArgumentError, "invalid #{op} matrix: #{inspect(other)}"
+      end
+
+    unless match?([_, _ | _], stops) do
+      raise ArgumentError, "#{op} needs at least two stops"
+    end
+
+    stops =
+      Enum.map_reduce(stops, 0, fn
+        {position, color}, last when is_number(position) and position >= last and position <= 1 ->
+          {:color_rgba, {r, g, b, a}} = Scenic.Color.to_rgba(color)
+          {<<position::float-32-big, r, g, b, a>>, position}
+
+        stop, _last ->
+          raise ArgumentError, "invalid #{op} stop: #{inspect(stop)}"
+      end)
+      |> elem(0)
+
+    [
+      <<kind::16-big, tile_mode::16-big, flags::16-big, length(stops)::16-big>>,
+      floats(geometry),
+      matrix,
+      stops
+    ]
+  end
+
+  defp gradient(op, _geometry, stops, opts) do
+    raise ArgumentError, "invalid #{op} arguments: #{inspect({stops, opts})}"
+  end
+
+  defp gradient_geometry(_op, {:linear, {x0, y0}, {x1, y1}}), do: {0x00, [x0, y0, x1, y1, 0, 0]}
+
+  defp gradient_geometry(_op, {:radial, {cx, cy}, inner, outer}),
+    do: {0x01, [cx, cy, inner, outer, 0, 0]}
+
+  defp gradient_geometry(_op, {:conical, {x0, y0}, r0, {x1, y1}, r1}),
+    do: {0x02, [x0, y0, r0, x1, y1, r1]}
+
+  defp gradient_geometry(op, {:sweep, center}),
+    do: gradient_geometry(op, {:sweep, center, 0, 360})
+
+  defp gradient_geometry(_op, {:sweep, {cx, cy}, start_angle, end_angle}),
+    do: {0x03, [cx, cy, start_angle, end_angle, 0, 0]}
+
+  defp gradient_geometry(op, geometry) do
+    raise ArgumentError, "invalid #{op} geometry: #{inspect(geometry)}"
+  end
+
+  defp tile_mode(_op, :clamp), do: 0x00
+  defp tile_mode(_op, :repeat), do: 0x01
+  defp tile_mode(_op, :mirror), do: 0x02
+  defp tile_mode(_op, :decal), do: 0x03
+
+  defp tile_mode(op, tile_mode) do
+    raise ArgumentError, "invalid #{op} tile mode: #{inspect(tile_mode)}"
+  end
+
+  defp floats(values) do
+    for value <- values, into: <<>> do
+      unless is_number(value) do
+        raise ArgumentError, "expected a number, got: #{inspect(value)}"
+      end
+
+      <<value::float-32-big>>
+    end
+  end
+
+  defp text_draw_mode(:fill), do: 0x00
+  defp text_draw_mode(:stroke), do: 0x01
+  defp text_draw_mode(:fill_stroke), do: 0x02
+
+  defp text_draw_mode(mode) do
+    raise ArgumentError, "invalid text_draw_mode: #{inspect(mode)}"
+  end
+
+  defp text_decoration(decorations) when is_list(decorations) do
+    Enum.reduce(decorations, 0, fn
+      :underline, acc -> acc ||| 0x01
+      :strikethrough, acc -> acc ||| 0x02
+      other, _acc -> raise ArgumentError, "invalid text_decoration: #{inspect(other)}"
+    end)
+  end
+
+  defp text_decoration(decorations) do
+    raise ArgumentError, "invalid text_decoration: #{inspect(decorations)}"
+  end
+
+  defp text_wrap_mode(:none), do: 0x00
+  defp text_wrap_mode(:word), do: 0x01
+  defp text_wrap_mode(:char), do: 0x02
+
+  defp text_wrap_mode(mode) do
+    raise ArgumentError, "invalid text_wrap mode: #{inspect(mode)}"
+  end
+
+  defp text_overflow(:clip), do: 0x00
+  defp text_overflow(:ellipsis), do: 0x01
+
+  defp text_overflow(overflow) do
+    raise ArgumentError, "invalid text_wrap overflow: #{inspect(overflow)}"
+  end
+end
@@ -19,7 +19,11 @@ defmodule Scenic.Driver.Skia.Native do
         _resizeable,
         _drm_card,
         _drm_hw_cursor,
-        _drm_input_log
+        _drm_input_log,
+        _max_fps,
+        _shared_fonts,
+        _image_cache_bytes,
+        _font_rendering
       ),
       do: :erlang.nif_error(:nif_not_loaded)
 
@@ -41,11 +45,37 @@ defmodule Scenic.Driver.Skia.Native do
   @doc false
   def del_script(_renderer, _id), do: :erlang.nif_error(:nif_not_loaded)
 
+  @doc false
+  def begin_update(_renderer), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def commit_update(_renderer), do: :erlang.nif_error(:nif_not_loaded)
+
   @doc false
   def put_static_image(_renderer, _id, _data), do: :erlang.nif_error(:nif_not_loaded)
 
   @doc false
-  def put_font(_renderer, _id, _data), do: :erlang.nif_error(:nif_not_loaded)
+  def del_static_image(_renderer, _id), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def put_font(_renderer, _id, _data, _face_index, _axes),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def del_font(_renderer, _id), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def set_font_fallback(_renderer, _font_ids, _system_fonts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def asset_loaded(_renderer, _kind, _id), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def measure_text(_renderer, _font_id, _size, _text), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def cache_stats(_renderer), do: :erlang.nif_error(:nif_not_loaded)
 
   @doc false
   def put_stream_texture(_renderer, _id, _format, _width, _height, _data),
@@ -66,6 +96,12 @@ defmodule Scenic.Driver.Skia.Native do
   @doc false
   def script_count(_renderer), do: :erlang.nif_error(:nif_not_loaded)
 
+  @doc false
+  def script_dependencies(_renderer, _id), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def set_frame_target(_renderer, _pid), do: :erlang.nif_error(:nif_not_loaded)
+
   @doc false
   def set_input_mask(_renderer, _mask), do: :erlang.nif_error(:nif_not_loaded)
 
@@ -0,0 +1,588 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{
+    Arc, Mutex, Weak,
+    atomic::{AtomicU64, Ordering},
+};
+
+use skia_safe::{Image, Typeface};
+
+use crate::text::FontRendering;
+
+/// Fonts shared by every driver started with `shared_fonts`.
+#[derive(Default)]
+struct FontPool {
+    fonts: Mutex<HashMap<String, Typeface>>,
+    /// Font generations of the drivers holding the pool. Changing a font bumps all of
+    /// them, so every driver re-shapes and re-records what it drew with the previous font
+    /// or its fallback.
+    holders: Mutex<Vec<Weak<AtomicU64>>>,
+}
+
+impl FontPool {
+    fn join(&self, generation: &Arc<AtomicU64>) {
+        if let Ok(mut holders) = self.holders.lock() {
+            holders.retain(|holder| holder.strong_count() > 0);
+            holders.push(Arc::downgrade(generation));
+        }
+    }
+
+    fn changed(&self) {
+        if let Ok(mut holders) = self.holders.lock() {
+            holders.retain(|holder| match holder.upgrade() {
+                Some(generation) => {
+                    generation.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                None => false,
+            });
+        }
+    }
+}
+
+/// The shared pool; freed once the last driver using it stops.
+static SHARED_FONT_POOL: Mutex<Weak<FontPool>> = Mutex::new(Weak::new());
+
+fn shared_font_pool() -> Arc<FontPool> {
+    let mut pool = SHARED_FONT_POOL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(pool) = pool.upgrade() {
+        return pool;
+    }
+    let shared = Arc::new(FontPool::default());
+    *pool = Arc::downgrade(&shared);
+    shared
+}
+
+/// Image and stream ids drawn by the current scene, with the number of scripts drawing
+/// each; these are never evicted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaRefs {
+    pub images: HashMap<String, usize>,
+    pub streams: HashMap<String, usize>,
+}
+
+impl MediaRefs {
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty() && self.streams.is_empty()
+    }
+
+    pub fn add(&mut self, other: &MediaRefs) {
+        for (refs, other) in [
+            (&mut self.images, &other.images),
+            (&mut self.streams, &other.streams),
+        ] {
+            for (id, count) in other {
+                *refs.entry(id.clone()).or_default() += count;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, other: &MediaRefs) {
+        for (refs, other) in [
+            (&mut self.images, &other.images),
+            (&mut self.streams, &other.streams),
+        ] {
+            for (id, count) in other {
+                if let Some(current) = refs.get_mut(id) {
+                    *current = current.saturating_sub(*count);
+                    if *current == 0 {
+                        refs.remove(id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ImageKind {
+    Static,
+    Stream,
+}
+
+struct CachedImage {
+    image: Image,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Static images and stream textures, sharing one byte budget.
+#[derive(Default)]
+struct ImageStore {
+    images: HashMap<String, CachedImage>,
+    streams: HashMap<String, CachedImage>,
+    /// Every image and stream keyed by its `last_used` tick, least recently used first.
+    lru: BTreeMap<u64, (ImageKind, String)>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl ImageStore {
+    fn map(&mut self, kind: ImageKind) -> &mut HashMap<String, CachedImage> {
+        match kind {
+            ImageKind::Static => &mut self.images,
+            ImageKind::Stream => &mut self.streams,
+        }
+    }
+
+    fn get(&mut self, kind: ImageKind, id: &str) -> Option<Image> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entries = match kind {
+            ImageKind::Static => &mut self.images,
+            ImageKind::Stream => &mut self.streams,
+        };
+        let entry = entries.get_mut(id)?;
+        let previous = std::mem::replace(&mut entry.last_used, clock);
+        let image = entry.image.clone();
+        if let Some(key) = self.lru.remove(&previous) {
+            self.lru.insert(clock, key);
+        }
+        Some(image)
+    }
+
+    fn insert(&mut self, kind: ImageKind, id: &str, image: Image) {
+        self.clock += 1;
+        let entry = CachedImage {
+            bytes: image_bytes(&image),
+            image,
+            last_used: self.clock,
+        };
+        self.bytes += entry.bytes;
+        if let Some(previous) = self.map(kind).insert(id.to_string(), entry) {
+            self.bytes -= previous.bytes;
+            self.lru.remove(&previous.last_used);
+        }
+        self.lru.insert(self.clock, (kind, id.to_string()));
+    }
+
+    fn remove(&mut self, kind: ImageKind, id: &str) -> bool {
+        match self.map(kind).remove(id) {
+            Some(entry) => {
+                self.bytes -= entry.bytes;
+                self.lru.remove(&entry.last_used);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evicts the least recently used images and streams that the scene does not draw
+    /// until the store fits `budget`, in one walk of the LRU index. Returns the number of
+    /// evicted entries.
+    fn evict_to(&mut self, budget: usize, refs: &MediaRefs) -> usize {
+        let mut bytes = self.bytes;
+        let mut evicted = Vec::new();
+        for (kind, id) in self.lru.values() {
+            if bytes <= budget {
+                break;
+            }
+            let (entries, referenced) = match kind {
+                ImageKind::Static => (&self.images, &refs.images),
+                ImageKind::Stream => (&self.streams, &refs.streams),
+            };
+            if referenced.contains_key(id) {
+                continue;
+            }
+            bytes -= entries[id].bytes;
+            evicted.push((*kind, id.clone()));
+        }
+        for (kind, id) in &evicted {
+            self.remove(*kind, id);
+        }
+        evicted.len()
+    }
+}
+
+fn image_bytes(image: &Image) -> usize {
+    let info = image.image_info();
+    (image.width().max(0) as usize) * (image.height().max(0) as usize) * info.bytes_per_pixel()
+}
+
+/// Fonts consulted, in order, for characters the selected font has no glyph for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FontFallback {
+    pub font_ids: Vec<String>,
+    /// Whether the system font manager is tried after `font_ids`.
+    pub system_fonts: bool,
+}
+
+/// Entry counts and sizes reported by the `cache_stats` NIF.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AssetStats {
+    pub images: usize,
+    pub image_bytes: usize,
+    pub streams: usize,
+    pub stream_bytes: usize,
+    pub fonts: usize,
+    pub budget_bytes: Option<usize>,
+}
+
+/// Static images, stream textures and fonts uploaded to one driver.
+///
+/// Owned by the driver handle and shared with its render thread; `clear` drops every
+/// asset when the driver stops. Images and streams count against an optional byte
+/// budget, see `trim`.
+#[derive(Default)]
+pub struct AssetCache {
+    store: Mutex<ImageStore>,
+    fonts: Mutex<HashMap<String, Typeface>>,
+    font_pool: Mutex<Option<Arc<FontPool>>>,
+    image_budget: Option<usize>,
+    fallback: Mutex<FontFallback>,
+    font_rendering: FontRendering,
+    /// Bumped whenever a font or the fallback chain changes, so text shaped with the
+    /// previous fonts is laid out again. Shared with the font pool, which bumps it when
+    /// another driver changes a shared font.
+    font_generation: Arc<AtomicU64>,
+}
+
+impl AssetCache {
+    pub fn new(shared_fonts: bool, image_budget: Option<usize>) -> Self {
+        Self::with_font_pool(shared_fonts.then(shared_font_pool), image_budget)
+    }
+
+    fn with_font_pool(font_pool: Option<Arc<FontPool>>, image_budget: Option<usize>) -> Self {
+        let font_generation = Arc::new(AtomicU64::new(0));
+        if let Some(pool) = &font_pool {
+            pool.join(&font_generation);
+        }
+        Self {
+            font_pool: Mutex::new(font_pool),
+            image_budget,
+            font_generation,
+            ..Self::default()
+        }
+    }
+
+    /// Sets how fonts are rasterized; fixed for the life of the driver.
+    pub fn with_font_rendering(mut self, font_rendering: FontRendering) -> Self {
+        self.font_rendering = font_rendering;
+        self
+    }
+
+    pub fn font_rendering(&self) -> FontRendering {
+        self.font_rendering
+    }
+
+    pub fn font_generation(&self) -> u64 {
+        self.font_generation.load(Ordering::Relaxed)
+    }
+
+    pub fn static_image(&self, id: &str) -> Option<Image> {
+        self.store.lock().ok()?.get(ImageKind::Static, id)
+    }
+
+    pub fn stream_image(&self, id: &str) -> Option<Image> {
+        self.store.lock().ok()?.get(ImageKind::Stream, id)
+    }
+
+    pub fn typeface(&self, id: &str) -> Option<Typeface> {
+        if let Ok(fonts) = self.fonts.lock()
+            && let Some(typeface) = fonts.get(id)
+        {
+            return Some(typeface.clone());
+        }
+        let pool = self.font_pool.lock().ok()?.clone()?;
+        let fonts = pool.fonts.lock().ok()?;
+        fonts.get(id).cloned()
+    }
+
+    pub fn has_static_image(&self, id: &str) -> bool {
+        self.store
+            .lock()
+            .is_ok_and(|store| store.images.contains_key(id))
+    }
+
+    pub fn has_stream_image(&self, id: &str) -> bool {
+        self.store
+            .lock()
+            .is_ok_and(|store| store.streams.contains_key(id))
+    }
+
+    pub fn has_font(&self, id: &str) -> bool {
+        self.typeface(id).is_some()
+    }
+
+    pub fn insert_static_image(&self, id: &str, image: Image) {
+        if let Ok(mut store) = self.store.lock() {
+            store.insert(ImageKind::Static, id, image);
+        }
+    }
+
+    pub fn remove_static_image(&self, id: &str) -> bool {
+        self.store
+            .lock()
+            .is_ok_and(|mut store| store.remove(ImageKind::Static, id))
+    }
+
+    pub fn insert_stream_image(&self, id: &str, image: Image) {
+        if let Ok(mut store) = self.store.lock() {
+            store.insert(ImageKind::Stream, id, image);
+        }
+    }
+
+    pub fn remove_stream_image(&self, id: &str) {
+        if let Ok(mut store) = self.store.lock() {
+            store.remove(ImageKind::Stream, id);
+        }
+    }
+
+    /// Stores a font in the shared pool when enabled, otherwise in this driver's cache.
+    pub fn insert_typeface(&self, id: &str, typeface: Typeface) -> Result<(), String> {
+        let pool = self
+            .font_pool
+            .lock()
+            .map_err(|_| "font cache lock poisoned".to_string())?
+            .clone();
+        let fonts = match pool.as_deref() {
+            Some(pool) => &pool.fonts,
+            None => &self.fonts,
+        };
+        fonts
+            .lock()
+            .map_err(|_| "font cache lock poisoned".to_string())?
+            .insert(id.to_string(), typeface);
+        match pool {
+            Some(pool) => pool.changed(),
+            None => {
+                self.font_generation.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a font from this driver's cache, or from the shared pool when enabled.
+    pub fn remove_font(&self, id: &str) -> Result<bool, String> {
+        let pool = self
+            .font_pool
+            .lock()
+            .map_err(|_| "font cache lock poisoned".to_string())?
+            .clone();
+        let removed_local = self
+            .fonts
+            .lock()
+            .map_err(|_| "font cache lock poisoned".to_string())?
+            .remove(id)
+            .is_some();
+        if removed_local {
+            self.font_generation.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut removed_shared = false;
+        if let Some(pool) = pool {
+            removed_shared = pool
+                .fonts
+                .lock()
+                .map_err(|_| "font cache lock poisoned".to_string())?
+                .remove(id)
+                .is_some();
+            if removed_shared {
+                pool.changed();
+            }
+        }
+        Ok(removed_local || removed_shared)
+    }
+
+    pub fn font_fallback(&self) -> FontFallback {
+        self.fallback
+            .lock()
+            .map(|fallback| fallback.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_font_fallback(&self, fallback: FontFallback) {
+        if let Ok(mut current) = self.fallback.lock() {
+            *current = fallback;
+        }
+        self.font_generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn over_budget(&self) -> bool {
+        self.image_budget
+            .is_some_and(|budget| self.store.lock().is_ok_and(|store| store.bytes > budget))
+    }
+
+    /// Evicts least recently used images and streams until the cache fits its budget.
+    /// Anything in `refs` stays, even if that leaves the cache over budget. Returns the
+    /// number of evicted entries.
+    pub fn trim(&self, refs: &MediaRefs) -> usize {
+        let Some(budget) = self.image_budget else {
+            return 0;
+        };
+        let Ok(mut store) = self.store.lock() else {
+            return 0;
+        };
+        store.evict_to(budget, refs)
+    }
+
+    pub fn stats(&self) -> AssetStats {
+        let mut stats = AssetStats {
+            budget_bytes: self.image_budget,
+            ..AssetStats::default()
+        };
+        if let Ok(store) = self.store.lock() {
+            stats.images = store.images.len();
+            stats.image_bytes = store.images.values().map(|entry| entry.bytes).sum();
+            stats.streams = store.streams.len();
+            stats.stream_bytes = store.streams.values().map(|entry| entry.bytes).sum();
+        }
+        stats.fonts = self.fonts.lock().map(|fonts| fonts.len()).unwrap_or(0);
+        let pool = self.font_pool.lock().ok().and_then(|pool| pool.clone());
+        if let Some(pool) = pool
+            && let Ok(fonts) = pool.fonts.lock()
+        {
+            stats.fonts += fonts.len();
+        }
+        stats
+    }
+
+    /// Drops every asset and releases this driver's hold on the shared font pool.
+    pub fn clear(&self) {
+        if let Ok(mut store) = self.store.lock() {
+            *store = ImageStore::default();
+        }
+        if let Ok(mut fonts) = self.fonts.lock() {
+            fonts.clear();
+        }
+        if let Ok(mut pool) = self.font_pool.lock() {
+            pool.take();
+        }
+        self.font_generation.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: i32, height: i32) -> Image {
+        let info = skia_safe::ImageInfo::new_n32_premul((width, height), None);
+        let pixels = vec![0xFF; (width * height * 4) as usize];
+        skia_safe::images::raster_from_data(
+            &info,
+            skia_safe::Data::new_copy(&pixels),
+            (width * 4) as usize,
+        )
+        .expect("image")
+    }
+
+    #[test]
+    fn caches_are_per_driver_and_cleared() {
+        let a = AssetCache::new(false, None);
+        let b = AssetCache::new(false, None);
+
+        a.insert_static_image("logo", image(1, 1));
+        assert!(a.static_image("logo").is_some());
+        assert!(b.static_image("logo").is_none());
+
+        let generation = a.font_generation();
+        a.clear();
+        assert!(a.static_image("logo").is_none());
+        assert!(a.font_generation() > generation);
+    }
+
+    #[test]
+    fn image_changes_keep_the_font_generation() {
+        let cache = AssetCache::new(false, None);
+        let font_generation = cache.font_generation();
+        cache.insert_static_image("logo", image(1, 1));
+        assert!(cache.remove_static_image("logo"));
+        assert_eq!(cache.font_generation(), font_generation);
+
+        cache.set_font_fallback(FontFallback::default());
+        assert!(cache.font_generation() > font_generation);
+    }
+
+    #[test]
+    fn shared_font_pool_lives_while_a_driver_uses_it() {
+        let a = AssetCache::new(true, None);
+        let b = AssetCache::new(true, None);
+        let local = AssetCache::new(false, None);
+        assert!(Arc::ptr_eq(
+            a.font_pool.lock().unwrap().as_ref().unwrap(),
+            b.font_pool.lock().unwrap().as_ref().unwrap()
+        ));
+        assert!(local.font_pool.lock().unwrap().is_none());
+
+        a.clear();
+        b.clear();
+        assert!(SHARED_FONT_POOL.lock().unwrap().upgrade().is_none());
+    }
+
+    #[test]
+    fn shared_font_changes_bump_every_holder() {
+        let pool = Arc::new(FontPool::default());
+        let a = AssetCache::with_font_pool(Some(Arc::clone(&pool)), None);
+        let b = AssetCache::with_font_pool(Some(Arc::clone(&pool)), None);
+        let typeface = crate::text::default_typeface().expect("default typeface");
+
+        let generation = b.font_generation();
+        a.insert_typeface("roboto", typeface).unwrap();
+        assert!(b.has_font("roboto"));
+        assert!(b.font_generation() > generation);
+
+        let generation = b.font_generation();
+        assert!(a.remove_font("roboto").unwrap());
+        assert!(b.font_generation() > generation);
+
+        drop(b);
+        a.insert_typeface("roboto", crate::text::default_typeface().unwrap())
+            .unwrap();
+        assert_eq!(pool.holders.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn trim_evicts_least_recently_used_unreferenced_images() {
+        // Each 4x4 image is 64 bytes; the budget holds two of them.
+        let cache = AssetCache::new(false, Some(128));
+        cache.insert_static_image("a", image(4, 4));
+        cache.insert_static_image("b", image(4, 4));
+        cache.insert_stream_image("c", image(4, 4));
+        assert!(cache.over_budget());
+
+        cache.static_image("a");
+        let refs = MediaRefs {
+            images: HashMap::new(),
+            streams: HashMap::from([("c".to_string(), 1)]),
+        };
+        assert_eq!(cache.trim(&refs), 1);
+        assert!(cache.has_static_image("a"));
+        assert!(!cache.has_static_image("b"));
+        assert!(cache.has_stream_image("c"));
+
+        let stats = cache.stats();
+        assert_eq!((stats.images, stats.streams), (1, 1));
+        assert_eq!(stats.image_bytes + stats.stream_bytes, 128);
+    }
+
+    #[test]
+    fn lru_index_follows_replaced_and_removed_images() {
+        let cache = AssetCache::new(false, Some(64));
+        cache.insert_static_image("a", image(4, 4));
+        cache.insert_stream_image("a", image(4, 4));
+        cache.insert_static_image("a", image(4, 4));
+        cache.static_image("a");
+        cache.remove_stream_image("a");
+        {
+            let store = cache.store.lock().unwrap();
+            let order: Vec<_> = store.lru.values().map(|(_, id)| id.as_str()).collect();
+            assert_eq!(order, ["a"]);
+            assert_eq!(store.lru.keys().next(), Some(&store.images["a"].last_used));
+        }
+        assert_eq!(cache.trim(&MediaRefs::default()), 0);
+    }
+
+    #[test]
+    fn referenced_images_are_kept_over_budget() {
+        let cache = AssetCache::new(false, Some(64));
+        cache.insert_static_image("a", image(4, 4));
+        cache.insert_static_image("b", image(4, 4));
+        let refs = MediaRefs {
+            images: HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]),
+            streams: HashMap::new(),
+        };
+        assert_eq!(cache.trim(&refs), 0);
+        assert!(cache.over_budget());
+    }
+}
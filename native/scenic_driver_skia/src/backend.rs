@@ -6,6 +6,7 @@ use std::{
         atomic::{AtomicBool, AtomicU32, Ordering},
         mpsc::Sender,
     },
+    time::{Duration, Instant},
 };
 
 use glutin::{
@@ -13,7 +14,9 @@ use glutin::{
     context::{ContextApi, ContextAttributesBuilder, NotCurrentGlContext, PossiblyCurrentContext},
     display::{GetGlDisplay, GlDisplay},
     prelude::GlSurface,
-    surface::{Surface as GlutinSurface, SurfaceAttributesBuilder, WindowSurface},
+    surface::{
+        Rect as GlutinRect, Surface as GlutinSurface, SurfaceAttributesBuilder, WindowSurface,
+    },
 };
 use glutin_winit::DisplayBuilder;
 use raw_window_handle::HasWindowHandle;
@@ -22,12 +25,13 @@ use winit::{
     application::ApplicationHandler,
     dpi::{LogicalPosition, LogicalSize},
     event::{ElementState, MouseScrollDelta, WindowEvent},
-    event_loop::{EventLoop, EventLoopProxy},
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy},
     keyboard::{Key, KeyLocation, ModifiersState, NamedKey},
     platform::wayland::EventLoopBuilderExtWayland,
     window::{Window, WindowAttributes},
 };
 
+use crate::assets::AssetCache;
 use crate::input::{
     ACTION_PRESS, ACTION_RELEASE, INPUT_MASK_CODEPOINT, INPUT_MASK_CURSOR_BUTTON,
     INPUT_MASK_CURSOR_POS, INPUT_MASK_CURSOR_SCROLL, INPUT_MASK_KEY, INPUT_MASK_VIEWPORT,
@@ -38,7 +42,8 @@ use crate::input_translate::{
     MouseButton as ScenicMouseButton, NamedKey as ScenicNamedKey, button_to_scenic, key_to_scenic,
     modifiers_to_mask,
 };
-use crate::renderer::{RenderState, Renderer};
+use crate::presentation::PresentationNotifier;
+use crate::renderer::{Renderer, SharedRenderState};
 
 #[derive(Debug)]
 pub enum UserEvent {
@@ -57,7 +62,7 @@ struct App {
     renderer: Option<Renderer>,
     running: bool,
     running_flag: Arc<AtomicBool>,
-    render_state: Arc<Mutex<RenderState>>,
+    render_state: SharedRenderState,
     input_mask: Arc<AtomicU32>,
     input_events: Arc<Mutex<InputQueue>>,
     cursor_pos: (f32, f32),
@@ -67,6 +72,12 @@ struct App {
     /// Tracks if we've sent an input notification this event loop iteration.
     /// Reset in about_to_wait to allow one notification per iteration.
     notified_this_iteration: bool,
+    min_frame_interval: Option<Duration>,
+    last_frame: Option<Instant>,
+    /// A redraw was requested before `min_frame_interval` elapsed.
+    frame_deferred: bool,
+    presentation: Arc<PresentationNotifier>,
+    assets: Arc<AssetCache>,
 }
 
 impl App {
@@ -106,25 +117,49 @@ impl App {
 
     fn redraw(&mut self) {
         if let (Some(env), Some(renderer)) = (self.env.as_mut(), self.renderer.as_mut()) {
-            // Use try_lock to avoid blocking the event loop if NIFs are updating render state.
-            // This prevents "Application Not Responding" when scene updates are being processed.
-            if let Ok(render_state) = self.render_state.try_lock() {
-                renderer.set_scale_factor(self.scale_factor as f32);
-                renderer.redraw(&render_state);
-                env.gl_surface
-                    .swap_buffers(&env.gl_context)
-                    .expect("swap_buffers failed");
-            } else {
-                // Lock not available - request another redraw to try again soon
-                env.window.request_redraw();
+            // The published snapshot is read without locking, so scene updates from NIFs
+            // never block or skip a frame.
+            let render_state = self.render_state.load();
+            renderer.set_scale_factor(self.scale_factor as f32);
+            let damage = renderer.redraw(&render_state, env.gl_surface.buffer_age());
+            // EGL damage rects use a bottom-left origin.
+            let height = self.window_size.1 as i32;
+            let rect = GlutinRect::new(
+                damage.left(),
+                height - damage.bottom(),
+                damage.width(),
+                damage.height(),
+            );
+            env.gl_surface
+                .swap_buffers_with_damage(&env.gl_context, &[rect])
+                .expect("swap_buffers failed");
+            self.last_frame = Some(Instant::now());
+            self.presentation.frame_presented();
+        }
+    }
+
+    fn next_frame_at(&self) -> Option<Instant> {
+        Some(self.last_frame? + self.min_frame_interval?)
+    }
+
+    /// Redraws now, or once `min_frame_interval` has passed since the last frame.
+    fn schedule_redraw(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        match self.next_frame_at() {
+            Some(deadline) if deadline > Instant::now() => {
+                self.frame_deferred = true;
+                event_loop.set_control_flow(ControlFlow::WaitUntil(deadline));
             }
+            _ => self.redraw(),
         }
     }
 
     fn set_running(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, running: bool) {
         if running && !self.running {
             if self.env.is_none() || self.renderer.is_none() {
-                match create_env_renderer_with_active_event_loop(event_loop) {
+                match create_env_renderer_with_active_event_loop(
+                    event_loop,
+                    Arc::clone(&self.assets),
+                ) {
                     Ok((env, renderer)) => {
                         let size = env.window.inner_size();
                         self.env = Some(env);
@@ -178,11 +213,13 @@ pub struct WaylandWindowConfig {
     pub requested_size: Option<(u32, u32)>,
     pub window_title: String,
     pub window_resizeable: bool,
+    pub min_frame_interval: Option<Duration>,
 }
 
 fn create_env_renderer_with_event_loop(
     event_loop: &EventLoop<UserEvent>,
     config: WaylandWindowConfig,
+    assets: Arc<AssetCache>,
 ) -> Result<(Env, Renderer), String> {
     let window_attributes = WindowAttributes::default()
         .with_title(config.window_title)
@@ -292,6 +329,7 @@ fn create_env_renderer_with_event_loop(
         gr_context,
         num_samples,
         stencil_size,
+        assets,
     );
 
     let env = Env {
@@ -305,6 +343,7 @@ fn create_env_renderer_with_event_loop(
 
 fn create_env_renderer_with_active_event_loop(
     event_loop: &winit::event_loop::ActiveEventLoop,
+    assets: Arc<AssetCache>,
 ) -> Result<(Env, Renderer), String> {
     let window_attributes = WindowAttributes::default()
         .with_title("skia-wayland-hello")
@@ -409,6 +448,7 @@ fn create_env_renderer_with_active_event_loop(
         gr_context,
         num_samples,
         stencil_size,
+        assets,
     );
 
     let env = Env {
@@ -584,25 +624,43 @@ impl ApplicationHandler<UserEvent> for App {
             UserEvent::Stop => self.set_running(event_loop, false),
             UserEvent::Redraw => {
                 if self.running {
-                    self.redraw();
+                    self.schedule_redraw(event_loop);
                 }
             }
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         // Reset notification flag at end of each event loop iteration.
         // This allows one input notification per iteration - responsive but not flooding.
         self.notified_this_iteration = false;
+
+        if self.frame_deferred {
+            match self.next_frame_at() {
+                Some(deadline) if deadline > Instant::now() => {
+                    event_loop.set_control_flow(ControlFlow::WaitUntil(deadline));
+                }
+                _ => {
+                    self.frame_deferred = false;
+                    event_loop.set_control_flow(ControlFlow::Wait);
+                    if self.running {
+                        self.redraw();
+                    }
+                }
+            }
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     proxy_ready: Sender<EventLoopProxy<UserEvent>>,
     running_flag: Arc<AtomicBool>,
-    render_state: Arc<Mutex<RenderState>>,
+    render_state: SharedRenderState,
     input_mask: Arc<AtomicU32>,
     input_events: Arc<Mutex<InputQueue>>,
+    presentation: Arc<PresentationNotifier>,
+    assets: Arc<AssetCache>,
     config: WaylandWindowConfig,
 ) {
     let mut el_builder = EventLoop::<UserEvent>::with_user_event();
@@ -610,14 +668,16 @@ pub fn run(
     let el = el_builder.build().expect("Failed to create event loop");
     let proxy = el.create_proxy();
     let _ = proxy_ready.send(proxy);
-    let (env, renderer) = match create_env_renderer_with_event_loop(&el, config) {
-        Ok(values) => values,
-        Err(err) => {
-            eprintln!("Failed to initialize renderer: {err}");
-            running_flag.store(false, Ordering::Relaxed);
-            return;
-        }
-    };
+    let min_frame_interval = config.min_frame_interval;
+    let (env, renderer) =
+        match create_env_renderer_with_event_loop(&el, config, Arc::clone(&assets)) {
+            Ok(values) => values,
+            Err(err) => {
+                eprintln!("Failed to initialize renderer: {err}");
+                running_flag.store(false, Ordering::Relaxed);
+                return;
+            }
+        };
     let size = env.window.inner_size();
     let scale_factor = env.window.scale_factor();
 
@@ -634,6 +694,11 @@ pub fn run(
         scale_factor,
         modifiers: ModifiersState::empty(),
         notified_this_iteration: false,
+        min_frame_interval,
+        last_frame: None,
+        frame_deferred: false,
+        presentation,
+        assets,
     };
     app.redraw();
     el.run_app(&mut app).expect("run_app failed");
@@ -0,0 +1,242 @@
+use std::collections::{HashMap, VecDeque};
+
+use skia_safe::{Color, IRect, Rect, RoundOut};
+
+/// Oldest back buffer whose contents can be repaired from the damage history.
+const MAX_BUFFER_AGE: usize = 4;
+
+/// Screen-space footprint of one `DrawScript` replay.
+///
+/// `rect` is `None` for scripts replayed op by op, whose bounds are unknown.
+/// `backdrop` is set for scripts that open a layer with a backdrop filter, which reads
+/// pixels outside the script's own bounds.
+#[derive(Clone, Debug)]
+pub struct ScriptBounds {
+    pub id: String,
+    pub revision: u64,
+    pub rect: Option<Rect>,
+    pub volatile: bool,
+    pub backdrop: bool,
+}
+
+impl ScriptBounds {
+    pub fn mapped(&self, matrix: &skia_safe::Matrix) -> Self {
+        Self {
+            rect: self.rect.map(|rect| matrix.map_rect(rect).0),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct FrameEntry {
+    revision: u64,
+    rect: Option<Rect>,
+    volatile: bool,
+}
+
+/// Tracks which parts of the surface changed between frames.
+///
+/// Each frame records the bounds of every script it draws. Comparing them with the
+/// previous frame yields the frame damage; the last few frames are kept so GL
+/// backends can repair back buffers of any age up to `MAX_BUFFER_AGE`.
+#[derive(Default)]
+pub struct DamageTracker {
+    previous: HashMap<String, FrameEntry>,
+    current: HashMap<String, FrameEntry>,
+    history: VecDeque<Option<IRect>>,
+    extra: Vec<Rect>,
+    clear_color: Option<Color>,
+    font_generation: u64,
+    force_full: bool,
+}
+
+impl DamageTracker {
+    /// Starts a frame. A font change can reflow text in any script, so it damages the
+    /// whole surface; image changes bump the revision of the scripts drawing them instead.
+    pub fn begin_frame(&mut self, clear_color: Color, font_generation: u64) {
+        if self.clear_color != Some(clear_color) || self.font_generation != font_generation {
+            self.force_full = true;
+        }
+        self.clear_color = Some(clear_color);
+        self.font_generation = font_generation;
+        self.current.clear();
+    }
+
+    /// Forces the next frame to redraw the whole surface.
+    pub fn invalidate(&mut self) {
+        self.force_full = true;
+    }
+
+    /// Adds a device-space rect to the next frame's damage, for content drawn outside
+    /// of scripts such as a software cursor.
+    pub fn add_damage(&mut self, rect: Rect) {
+        self.extra.push(rect);
+    }
+
+    pub fn note(&mut self, bounds: &ScriptBounds) {
+        // A backdrop blur shows whatever changed behind it, possibly well outside the
+        // layer, so frames drawing one are redrawn in full.
+        if bounds.backdrop {
+            self.force_full = true;
+        }
+        let entry = self
+            .current
+            .entry(bounds.id.clone())
+            .or_insert_with(|| FrameEntry {
+                revision: bounds.revision,
+                rect: Some(Rect::new_empty()),
+                volatile: false,
+            });
+        entry.revision = bounds.revision;
+        entry.volatile |= bounds.volatile;
+        entry.rect = match (entry.rect, bounds.rect) {
+            (Some(mut rect), Some(other)) => {
+                rect.join(other);
+                Some(rect)
+            }
+            _ => None,
+        };
+    }
+
+    /// Finishes the frame and returns the region of a back buffer of `buffer_age` that
+    /// must be redrawn. An age of 0 means the buffer contents are undefined.
+    pub fn finish_frame(&mut self, bounds: IRect, buffer_age: u32) -> IRect {
+        let frame_damage = self.frame_damage().map(|rect| {
+            if rect.is_empty() {
+                return IRect::default();
+            }
+            // Antialiased edges can bleed one pixel past the recorded bounds.
+            let rect: IRect = rect.round_out();
+            IRect::intersect(&rect.with_outset((1, 1)), &bounds).unwrap_or_default()
+        });
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.extra.clear();
+        self.force_full = false;
+
+        let age = buffer_age as usize;
+        let region = if age == 0 || age > self.history.len() + 1 {
+            None
+        } else {
+            self.history
+                .iter()
+                .take(age - 1)
+                .try_fold(frame_damage.unwrap_or(bounds), |region, damage| {
+                    Some(IRect::join(&region, &(*damage)?))
+                })
+        };
+
+        self.history.push_front(frame_damage);
+        self.history.truncate(MAX_BUFFER_AGE);
+
+        region.unwrap_or(bounds)
+    }
+
+    fn frame_damage(&self) -> Option<Rect> {
+        if self.force_full {
+            return None;
+        }
+
+        let mut damage = Rect::new_empty();
+        for (id, entry) in &self.current {
+            match self.previous.get(id) {
+                Some(previous) if previous == entry && !entry.volatile => {}
+                Some(previous) => {
+                    damage.join(previous.rect?);
+                    damage.join(entry.rect?);
+                }
+                None => damage.join(entry.rect?),
+            }
+        }
+        for (id, previous) in &self.previous {
+            if !self.current.contains_key(id) {
+                damage.join(previous.rect?);
+            }
+        }
+        for rect in &self.extra {
+            damage.join(rect);
+        }
+        Some(damage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: IRect = IRect {
+        left: 0,
+        top: 0,
+        right: 100,
+        bottom: 100,
+    };
+
+    fn frame(tracker: &mut DamageTracker, scripts: &[(&str, u64, Rect)], age: u32) -> IRect {
+        tracker.begin_frame(Color::BLACK, 0);
+        for (id, revision, rect) in scripts {
+            tracker.note(&ScriptBounds {
+                id: id.to_string(),
+                revision: *revision,
+                rect: Some(*rect),
+                volatile: false,
+                backdrop: false,
+            });
+        }
+        tracker.finish_frame(BOUNDS, age)
+    }
+
+    #[test]
+    fn first_frame_is_fully_damaged() {
+        let mut tracker = DamageTracker::default();
+        let rect = Rect::from_xywh(10.0, 10.0, 5.0, 5.0);
+        assert_eq!(frame(&mut tracker, &[("a", 1, rect)], 1), BOUNDS);
+    }
+
+    #[test]
+    fn changed_script_damages_old_and_new_bounds() {
+        let mut tracker = DamageTracker::default();
+        let clock = Rect::from_xywh(10.0, 10.0, 5.0, 5.0);
+        let panel = Rect::from_xywh(50.0, 50.0, 40.0, 40.0);
+        frame(&mut tracker, &[("clock", 1, clock), ("panel", 2, panel)], 1);
+
+        assert!(frame(&mut tracker, &[("clock", 1, clock), ("panel", 2, panel)], 1).is_empty());
+
+        let moved = Rect::from_xywh(20.0, 10.0, 5.0, 5.0);
+        assert_eq!(
+            frame(&mut tracker, &[("clock", 3, moved), ("panel", 2, panel)], 1),
+            IRect::new(9, 9, 26, 16)
+        );
+    }
+
+    #[test]
+    fn older_buffers_include_previous_damage() {
+        let mut tracker = DamageTracker::default();
+        let rect = Rect::from_xywh(10.0, 10.0, 5.0, 5.0);
+        frame(&mut tracker, &[("a", 1, rect)], 1);
+        frame(&mut tracker, &[("a", 1, rect)], 1);
+        frame(&mut tracker, &[("a", 2, rect)], 1);
+
+        assert_eq!(
+            frame(&mut tracker, &[("a", 2, rect)], 2),
+            IRect::new(9, 9, 16, 16)
+        );
+        assert_eq!(frame(&mut tracker, &[("a", 2, rect)], 0), BOUNDS);
+    }
+
+    #[test]
+    fn backdrop_layers_damage_the_whole_surface() {
+        let mut tracker = DamageTracker::default();
+        let rect = Rect::from_xywh(10.0, 10.0, 5.0, 5.0);
+        frame(&mut tracker, &[("a", 1, rect)], 1);
+
+        tracker.begin_frame(Color::BLACK, 0);
+        tracker.note(&ScriptBounds {
+            id: "a".to_string(),
+            revision: 1,
+            rect: Some(rect),
+            volatile: false,
+            backdrop: true,
+        });
+        assert_eq!(tracker.finish_frame(BOUNDS, 1), BOUNDS);
+    }
+}
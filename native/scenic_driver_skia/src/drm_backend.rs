@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::ffi::CString;
 use std::fs::{File, OpenOptions};
-use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
 use std::os::raw::c_void;
 use std::ptr;
 use std::sync::{
@@ -22,14 +22,19 @@ use gbm::{
 use glutin_egl_sys::egl;
 use glutin_egl_sys::egl::types::{EGLConfig, EGLContext, EGLDisplay, EGLSurface, EGLenum, EGLint};
 use libloading::Library;
-use skia_safe::{Color, Paint, PaintStyle, gpu::gl::FramebufferInfo};
+use skia_safe::{Color, Paint, PaintStyle, Rect, gpu::gl::FramebufferInfo};
 
+use crate::assets::AssetCache;
 use crate::cursor::CursorState;
 use crate::drm_input::DrmInput;
+use crate::frame_signal::FrameSignal;
 use crate::input::{InputEvent, InputQueue, notify_input_ready};
-use crate::renderer::{RenderState, Renderer};
+use crate::presentation::PresentationNotifier;
+use crate::renderer::{Renderer, SharedRenderState};
 
 const EGL_PLATFORM_GBM_KHR: EGLenum = 0x31D7;
+const EGL_BUFFER_AGE_EXT: EGLint = 0x313D;
+const SOFTWARE_CURSOR_EXTENT: f32 = 6.0;
 
 struct Card(File);
 
@@ -609,7 +614,11 @@ fn init_egl(
     Ok((display, context, surface))
 }
 
-fn create_renderer(egl: &egl::Egl, dimensions: (u32, u32)) -> Result<Renderer, String> {
+fn create_renderer(
+    egl: &egl::Egl,
+    dimensions: (u32, u32),
+    assets: Arc<AssetCache>,
+) -> Result<Renderer, String> {
     gl::load_with(|s| unsafe {
         let symbol = CString::new(s).expect("gl symbol");
         egl.GetProcAddress(symbol.as_ptr()) as *const _
@@ -637,7 +646,7 @@ fn create_renderer(egl: &egl::Egl, dimensions: (u32, u32)) -> Result<Renderer, S
         }
     };
 
-    Ok(Renderer::new(dimensions, fb_info, gr_context, 0, 0))
+    Ok(Renderer::new(dimensions, fb_info, gr_context, 0, 0, assets))
 }
 
 fn framebuffer_for_bo(
@@ -664,11 +673,68 @@ fn cursor_snapshot(cursor_state: &Arc<Mutex<CursorState>>) -> CursorState {
         .unwrap_or_else(|_| CursorState::new())
 }
 
-fn draw_software_cursor(renderer: &mut Renderer, cursor_pos: (f32, f32), screen_size: (u32, u32)) {
+fn buffer_age(egl_state: &EglState) -> u32 {
+    let mut age: EGLint = 0;
+    let ok = unsafe {
+        egl_state.egl.QuerySurface(
+            egl_state.display,
+            egl_state.surface,
+            EGL_BUFFER_AGE_EXT,
+            &mut age,
+        )
+    };
+    if ok == egl::FALSE {
+        0
+    } else {
+        age.max(0) as u32
+    }
+}
+
+fn software_cursor_pos(cursor_pos: (f32, f32), screen_size: (u32, u32)) -> (f32, f32) {
     let (width, height) = screen_size;
-    let x = cursor_pos.0.clamp(0.0, width.saturating_sub(1) as f32);
-    let y = cursor_pos.1.clamp(0.0, height.saturating_sub(1) as f32);
+    (
+        cursor_pos.0.clamp(0.0, width.saturating_sub(1) as f32),
+        cursor_pos.1.clamp(0.0, height.saturating_sub(1) as f32),
+    )
+}
+
+fn software_cursor_rect(cursor_pos: (f32, f32)) -> Rect {
+    Rect::from_xywh(
+        cursor_pos.0 - SOFTWARE_CURSOR_EXTENT,
+        cursor_pos.1 - SOFTWARE_CURSOR_EXTENT,
+        SOFTWARE_CURSOR_EXTENT * 2.0,
+        SOFTWARE_CURSOR_EXTENT * 2.0,
+    )
+}
+
+/// Redraws the damaged region of the back buffer and draws the software cursor on top.
+///
+/// `drawn_cursor` tracks where the software cursor was last drawn so the area it
+/// covered is repainted once it moves or hides.
+fn render_frame(
+    renderer: &mut Renderer,
+    render_state: &SharedRenderState,
+    buffer_age: u32,
+    software_cursor: Option<(f32, f32)>,
+    drawn_cursor: &mut Option<(f32, f32)>,
+    screen_size: (u32, u32),
+) {
+    let software_cursor = software_cursor.map(|pos| software_cursor_pos(pos, screen_size));
+    if let Some(pos) = drawn_cursor.take() {
+        renderer.add_damage(software_cursor_rect(pos));
+    }
+    if let Some(pos) = software_cursor {
+        renderer.add_damage(software_cursor_rect(pos));
+    }
 
+    renderer.redraw(&render_state.load(), buffer_age);
+    if let Some(pos) = software_cursor {
+        draw_software_cursor(renderer, pos);
+        *drawn_cursor = Some(pos);
+    }
+}
+
+fn draw_software_cursor(renderer: &mut Renderer, (x, y): (f32, f32)) {
     let canvas = renderer.surface_mut().canvas();
     let mut fill = Paint::default();
     fill.set_anti_alias(true);
@@ -690,12 +756,33 @@ pub struct DrmRunConfig {
     pub card_path: Option<String>,
     pub hw_cursor: bool,
     pub input_log: bool,
+    pub min_frame_interval: Option<Duration>,
+    pub presentation: Arc<PresentationNotifier>,
+    pub assets: Arc<AssetCache>,
+}
+
+/// Blocks until the frame signal or an input device becomes readable, or `timeout`
+/// elapses.
+fn wait_for_events(frame_fd: Option<RawFd>, input_fds: &[RawFd], timeout: Duration) {
+    let mut fds: Vec<libc::pollfd> = frame_fd
+        .into_iter()
+        .chain(input_fds.iter().copied())
+        .map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout_ms = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+    unsafe {
+        libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms);
+    }
 }
 
 pub fn run(
     stop: Arc<AtomicBool>,
-    dirty: Arc<AtomicBool>,
-    render_state: Arc<Mutex<RenderState>>,
+    frame_signal: Arc<FrameSignal>,
+    render_state: SharedRenderState,
     input_mask: Arc<AtomicU32>,
     input_events: Arc<Mutex<InputQueue>>,
     config: DrmRunConfig,
@@ -874,14 +961,15 @@ pub fn run(
             surface,
         };
 
-        let mut renderer = match create_renderer(&egl_state.egl, dimensions) {
-            Ok(renderer) => renderer,
-            Err(e) => {
-                eprintln!("DRM backend unavailable: {e}");
-                std::thread::sleep(Duration::from_millis(250));
-                continue;
-            }
-        };
+        let mut renderer =
+            match create_renderer(&egl_state.egl, dimensions, Arc::clone(&config.assets)) {
+                Ok(renderer) => renderer,
+                Err(e) => {
+                    eprintln!("DRM backend unavailable: {e}");
+                    std::thread::sleep(Duration::from_millis(250));
+                    continue;
+                }
+            };
 
         let mode_blob = match card.create_property_blob(&mode) {
             Ok(blob) => blob,
@@ -894,13 +982,16 @@ pub fn run(
 
         let mut framebuffer_cache: HashMap<u32, framebuffer::Handle> = HashMap::new();
 
-        if let Ok(state) = render_state.lock() {
-            renderer.redraw(&state);
-        }
         let mut cursor = cursor_snapshot(&config.cursor_state);
-        if cursor_plane.is_none() && cursor.visible {
-            draw_software_cursor(&mut renderer, cursor.pos, dimensions);
-        }
+        let mut drawn_cursor = None;
+        render_frame(
+            &mut renderer,
+            &render_state,
+            buffer_age(&egl_state),
+            (cursor_plane.is_none() && cursor.visible).then_some(cursor.pos),
+            &mut drawn_cursor,
+            dimensions,
+        );
 
         if unsafe {
             egl_state
@@ -957,6 +1048,8 @@ pub fn run(
             std::thread::sleep(Duration::from_millis(250));
             continue;
         }
+        // The blocking modeset commit scans out the first frame.
+        config.presentation.frame_presented();
 
         let mut current_bo = Some(bo);
         let mut last_cursor = cursor;
@@ -968,10 +1061,13 @@ pub fn run(
         {
             eprintln!("DRM cursor update failed: {err}");
             cursor_plane = None;
-            dirty.store(true, Ordering::Relaxed);
+            frame_signal.notify();
         }
 
         let mut next_hotplug_check = Instant::now() + hotplug_interval;
+        let mut next_frame_at = config
+            .min_frame_interval
+            .map(|interval| Instant::now() + interval);
 
         loop {
             if stop.load(Ordering::Relaxed) {
@@ -1012,25 +1108,28 @@ pub fn run(
                     {
                         eprintln!("DRM cursor update failed: {err}");
                         cursor_plane = None;
-                        dirty.store(true, Ordering::Relaxed);
+                        frame_signal.notify();
                     }
                 }
             } else {
                 if cursor.visible && cursor.pos != last_cursor.pos {
-                    dirty.store(true, Ordering::Relaxed);
+                    frame_signal.notify();
                 }
                 if cursor.visible != last_cursor.visible {
-                    dirty.store(true, Ordering::Relaxed);
+                    frame_signal.notify();
                 }
             }
             last_cursor = cursor;
-            if dirty.swap(false, Ordering::Relaxed) {
-                if let Ok(state) = render_state.lock() {
-                    renderer.redraw(&state);
-                }
-                if cursor_plane.is_none() && cursor.visible {
-                    draw_software_cursor(&mut renderer, cursor.pos, dimensions);
-                }
+            let frame_due = next_frame_at.is_none_or(|at| Instant::now() >= at);
+            if frame_due && frame_signal.take() {
+                render_frame(
+                    &mut renderer,
+                    &render_state,
+                    buffer_age(&egl_state),
+                    (cursor_plane.is_none() && cursor.visible).then_some(cursor.pos),
+                    &mut drawn_cursor,
+                    dimensions,
+                );
 
                 if unsafe {
                     egl_state
@@ -1058,25 +1157,32 @@ pub fn run(
                     }
                 };
 
-                let mut flip_req = atomic::AtomicModeReq::new();
-                if let Err(e) =
-                    add_plane_properties(&mut flip_req, plane, &plane_props, crtc_handle, next_fb)
-                {
-                    eprintln!("DRM backend unavailable: {e}");
-                    break;
-                }
-
-                if let Err(e) = card.atomic_commit(
-                    AtomicCommitFlags::NONBLOCK | AtomicCommitFlags::PAGE_FLIP_EVENT,
-                    flip_req,
-                ) {
-                    let err = e.to_string();
-                    if is_ebusy(&err) {
-                        drop(next_bo);
-                        std::thread::sleep(Duration::from_millis(2));
-                        continue;
+                // The frame is already drawn and its damage recorded, so a busy plane
+                // retries the flip with the same buffer instead of rendering again.
+                let flipped = loop {
+                    let mut flip_req = atomic::AtomicModeReq::new();
+                    if let Err(e) = add_plane_properties(
+                        &mut flip_req,
+                        plane,
+                        &plane_props,
+                        crtc_handle,
+                        next_fb,
+                    ) {
+                        break Err(e);
                     }
-                    eprintln!("DRM backend unavailable: {err}");
+                    match card.atomic_commit(
+                        AtomicCommitFlags::NONBLOCK | AtomicCommitFlags::PAGE_FLIP_EVENT,
+                        flip_req,
+                    ) {
+                        Ok(()) => break Ok(()),
+                        Err(e) if is_ebusy(&e.to_string()) => {
+                            std::thread::sleep(Duration::from_millis(2));
+                        }
+                        Err(e) => break Err(e.to_string()),
+                    }
+                };
+                if let Err(e) = flipped {
+                    eprintln!("DRM backend unavailable: {e}");
                     break;
                 }
 
@@ -1084,11 +1190,31 @@ pub fn run(
                     eprintln!("DRM backend unavailable: {e}");
                     break;
                 }
+                config.presentation.frame_presented();
 
                 drop(current_bo.take());
                 current_bo = Some(next_bo);
+                next_frame_at = config
+                    .min_frame_interval
+                    .map(|interval| Instant::now() + interval);
             }
-            std::thread::sleep(Duration::from_millis(4));
+
+            // While a pending frame is held back by the frame interval, leave the frame
+            // signal out of the poll set so it doesn't wake the loop immediately.
+            let now = Instant::now();
+            let pacing = next_frame_at
+                .map(|at| at.saturating_duration_since(now))
+                .unwrap_or_default();
+            let throttled = !pacing.is_zero() && frame_signal.is_pending();
+            let mut timeout = next_hotplug_check.saturating_duration_since(now);
+            if throttled {
+                timeout = timeout.min(pacing);
+            }
+            wait_for_events(
+                (!throttled).then(|| frame_signal.raw_fd()),
+                &input.wait_fds(),
+                timeout,
+            );
         }
 
         continue;
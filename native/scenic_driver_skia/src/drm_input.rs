@@ -1,5 +1,5 @@
 use std::fs;
-use std::os::fd::AsRawFd;
+use std::os::fd::{AsRawFd, RawFd};
 use std::path::Path;
 use std::sync::{
     Arc, Mutex,
@@ -79,6 +79,18 @@ impl DrmInput {
         }
     }
 
+    /// Device descriptors to wait on for input. Empty while input is masked off, since
+    /// `poll` leaves events queued in that case.
+    pub fn wait_fds(&self) -> Vec<RawFd> {
+        if self.input_mask.load(Ordering::Relaxed) == 0 {
+            return Vec::new();
+        }
+        self.devices
+            .iter()
+            .map(|device| device.device.as_raw_fd())
+            .collect()
+    }
+
     pub fn poll(&mut self) {
         let mask = self.input_mask.load(Ordering::Relaxed);
         if mask == 0 {
@@ -0,0 +1,640 @@
+//! Driver extension ops: Skia features Scenic's script format has no opcode for.
+//!
+//! Extensions use opcodes `0xF000..=0xFFFF`, the low 12 bits naming the extension. Each
+//! is encoded as
+//!
+//! ```text
+//! opcode::16, version::16, length::32, payload::length, padding to 4 bytes
+//! ```
+//!
+//! so the parser can step over extensions it doesn't know, or versions newer than the
+//! ones it understands, instead of rejecting the whole script. Every extension is at
+//! version 1 so far; a payload change bumps the version and adds a match arm for it
+//! next to the older ones, which keep parsing.
+
+use skia_safe::{BlendMode, Color, Matrix, Point, Rect, TileMode};
+
+use crate::renderer::{
+    Blur, DropShadow, Gradient, GradientKind, Layer, ScriptOp, TextDecoration, TextDrawMode,
+    TextOverflow, TextWrap, TextWrapMode,
+};
+
+pub const FIRST_OPCODE: u16 = 0xF000;
+
+/// Bytes between the opcode and the payload.
+const HEADER_LEN: usize = 6;
+
+const TEXT_DRAW_MODE: u16 = 0x001;
+const TEXT_DECORATION: u16 = 0x002;
+const LETTER_SPACING: u16 = 0x003;
+const TEXT_WRAP: u16 = 0x004;
+const PUSH_LAYER: u16 = 0x005;
+const BLEND_MODE: u16 = 0x006;
+const DROP_SHADOW: u16 = 0x007;
+const BLUR: u16 = 0x008;
+const BACKDROP_BLUR: u16 = 0x009;
+const FILL_GRADIENT: u16 = 0x00A;
+const STROKE_GRADIENT: u16 = 0x00B;
+
+/// Blend modes in wire order, which follows Skia's `SkBlendMode`.
+const BLEND_MODES: [BlendMode; 29] = [
+    BlendMode::Clear,
+    BlendMode::Src,
+    BlendMode::Dst,
+    BlendMode::SrcOver,
+    BlendMode::DstOver,
+    BlendMode::SrcIn,
+    BlendMode::DstIn,
+    BlendMode::SrcOut,
+    BlendMode::DstOut,
+    BlendMode::SrcATop,
+    BlendMode::DstATop,
+    BlendMode::Xor,
+    BlendMode::Plus,
+    BlendMode::Modulate,
+    BlendMode::Screen,
+    BlendMode::Overlay,
+    BlendMode::Darken,
+    BlendMode::Lighten,
+    BlendMode::ColorDodge,
+    BlendMode::ColorBurn,
+    BlendMode::HardLight,
+    BlendMode::SoftLight,
+    BlendMode::Difference,
+    BlendMode::Exclusion,
+    BlendMode::Multiply,
+    BlendMode::Hue,
+    BlendMode::Saturation,
+    BlendMode::Color,
+    BlendMode::Luminosity,
+];
+
+pub fn is_extension_opcode(opcode: u16) -> bool {
+    opcode >= FIRST_OPCODE
+}
+
+/// Parses the extension op `opcode` from `rest`, the bytes after the opcode. Returns
+/// `None` for extensions that are skipped, along with the bytes after the op.
+pub fn parse(opcode: u16, rest: &[u8]) -> Result<(Option<ScriptOp>, &[u8]), String> {
+    if rest.len() < HEADER_LEN {
+        return Err(format!("extension 0x{opcode:04x} header truncated"));
+    }
+    let (header, tail) = rest.split_at(HEADER_LEN);
+    let version = u16::from_be_bytes([header[0], header[1]]);
+    let len = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+    let pad = (4 - (len % 4)) % 4;
+    if tail.len() < len + pad {
+        return Err(format!("extension 0x{opcode:04x} payload truncated"));
+    }
+    let (payload, tail) = tail.split_at(len);
+    let tail = &tail[pad..];
+
+    let op = match (opcode - FIRST_OPCODE, version) {
+        (TEXT_DRAW_MODE, 1) => text_draw_mode(Payload::new("text_draw_mode", payload))?,
+        (TEXT_DECORATION, 1) => text_decoration(Payload::new("text_decoration", payload))?,
+        (LETTER_SPACING, 1) => letter_spacing(Payload::new("letter_spacing", payload))?,
+        (TEXT_WRAP, 1) => text_wrap(Payload::new("text_wrap", payload))?,
+        (PUSH_LAYER, 1) => push_layer(Payload::new("push_layer", payload))?,
+        (BLEND_MODE, 1) => blend_mode(Payload::new("blend_mode", payload))?,
+        (DROP_SHADOW, 1) => drop_shadow(Payload::new("drop_shadow", payload))?,
+        (BLUR, 1) => ScriptOp::Blur(blur(Payload::new("blur", payload))?),
+        (BACKDROP_BLUR, 1) => ScriptOp::BackdropBlur(blur(Payload::new("backdrop_blur", payload))?),
+        (FILL_GRADIENT, 1) => {
+            ScriptOp::FillGradient(gradient(Payload::new("fill_gradient", payload))?)
+        }
+        (STROKE_GRADIENT, 1) => {
+            ScriptOp::StrokeGradient(gradient(Payload::new("stroke_gradient", payload))?)
+        }
+        _ => return Ok((None, tail)),
+    };
+    Ok((Some(op), tail))
+}
+
+/// Checks that every `BackdropBlur` is followed by the `PushLayer` it applies to. A
+/// backdrop filter only exists on a layer, so one left before other drawing would be
+/// silently ignored; only the layer's other effects may come in between.
+pub fn check_backdrop_blur(ops: &[ScriptOp]) -> Result<(), String> {
+    let mut pending = false;
+    for op in ops {
+        match op {
+            ScriptOp::BackdropBlur(blur) => pending = blur.is_some(),
+            ScriptOp::PushLayer(_) => pending = false,
+            ScriptOp::BlendMode(_) | ScriptOp::DropShadow(_) | ScriptOp::Blur(_) => {}
+            _ if pending => return Err("backdrop_blur must be followed by push_layer".to_string()),
+            _ => {}
+        }
+    }
+    if pending {
+        return Err("backdrop_blur must be followed by push_layer".to_string());
+    }
+    Ok(())
+}
+
+fn text_draw_mode(payload: Payload) -> Result<ScriptOp, String> {
+    let mode = match payload.u16(0)? {
+        0x00 => TextDrawMode::Fill,
+        0x01 => TextDrawMode::Stroke,
+        0x02 => TextDrawMode::FillStroke,
+        _ => return Err("unsupported text_draw_mode value".to_string()),
+    };
+    Ok(ScriptOp::TextDrawMode(mode))
+}
+
+fn text_decoration(payload: Payload) -> Result<ScriptOp, String> {
+    let flags = payload.u16(0)?;
+    if flags & !0x03 != 0 {
+        return Err("unsupported text_decoration flags".to_string());
+    }
+    Ok(ScriptOp::TextDecoration(TextDecoration {
+        underline: flags & 0x01 != 0,
+        strikethrough: flags & 0x02 != 0,
+    }))
+}
+
+fn letter_spacing(payload: Payload) -> Result<ScriptOp, String> {
+    Ok(ScriptOp::LetterSpacing(payload.f32(0)?))
+}
+
+fn text_wrap(payload: Payload) -> Result<ScriptOp, String> {
+    let mode = match payload.u16(0)? {
+        0x00 => TextWrapMode::None,
+        0x01 => TextWrapMode::Word,
+        0x02 => TextWrapMode::Char,
+        _ => return Err("unsupported text_wrap mode".to_string()),
+    };
+    let overflow = match payload.u16(2)? {
+        0x00 => TextOverflow::Clip,
+        0x01 => TextOverflow::Ellipsis,
+        _ => return Err("unsupported text_wrap overflow".to_string()),
+    };
+    let max_lines = payload.u16(4)?;
+    let max_width = payload.f32(8)?;
+    // A zero width turns wrapping off.
+    let wrap = (max_width.is_finite() && max_width > 0.0).then_some(TextWrap {
+        max_width,
+        mode,
+        overflow,
+        max_lines,
+    });
+    Ok(ScriptOp::TextWrap(wrap))
+}
+
+fn push_layer(payload: Payload) -> Result<ScriptOp, String> {
+    let alpha = payload.f32(0)?;
+    if !(0.0..=1.0).contains(&alpha) {
+        return Err("push_layer alpha out of range".to_string());
+    }
+    let bounds = match payload.u16(4)? {
+        0x00 => None,
+        0x01 => Some(Rect::from_xywh(
+            payload.f32(8)?,
+            payload.f32(12)?,
+            payload.f32(16)?,
+            payload.f32(20)?,
+        )),
+        _ => return Err("unsupported push_layer flags".to_string()),
+    };
+    Ok(ScriptOp::PushLayer(Layer { alpha, bounds }))
+}
+
+fn blend_mode(payload: Payload) -> Result<ScriptOp, String> {
+    let mode = BLEND_MODES
+        .get(payload.u16(0)? as usize)
+        .ok_or_else(|| "unsupported blend_mode value".to_string())?;
+    Ok(ScriptOp::BlendMode(*mode))
+}
+
+/// An empty payload turns the shadow off.
+fn drop_shadow(payload: Payload) -> Result<ScriptOp, String> {
+    if payload.bytes.is_empty() {
+        return Ok(ScriptOp::DropShadow(None));
+    }
+    let [r, g, b, a] = payload.field(16)?;
+    Ok(ScriptOp::DropShadow(Some(DropShadow {
+        dx: payload.f32(0)?,
+        dy: payload.f32(4)?,
+        sigma_x: payload.sigma(8)?,
+        sigma_y: payload.sigma(12)?,
+        color: Color::from_argb(a, r, g, b),
+    })))
+}
+
+/// An empty payload turns the blur off.
+fn blur(payload: Payload) -> Result<Option<Blur>, String> {
+    if payload.bytes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Blur {
+        sigma_x: payload.sigma(0)?,
+        sigma_y: payload.sigma(4)?,
+    }))
+}
+
+/// Parses a gradient:
+///
+/// ```text
+/// kind::16, tile_mode::16, flags::16, stop_count::16, geometry::6*f32,
+/// [a, b, c, d, e, f]::6*f32 when flags has 0x01,
+/// stop_count * (position::f32, r::8, g::8, b::8, a::8)
+/// ```
+///
+/// The geometry is `x0, y0, x1, y1` for linear gradients, `cx, cy, inner_radius,
+/// outer_radius` for radial ones, `x0, y0, r0, x1, y1, r1` for conical ones and `cx, cy,
+/// start_angle, end_angle` for sweeps, with unused fields zero.
+fn gradient(payload: Payload) -> Result<Gradient, String> {
+    let geometry = |index: usize| payload.f32(8 + index * 4);
+    let kind = match payload.u16(0)? {
+        0x00 => GradientKind::Linear {
+            start: Point::new(geometry(0)?, geometry(1)?),
+            end: Point::new(geometry(2)?, geometry(3)?),
+        },
+        0x01 => GradientKind::Radial {
+            center: Point::new(geometry(0)?, geometry(1)?),
+            inner_radius: geometry(2)?,
+            outer_radius: geometry(3)?,
+        },
+        0x02 => GradientKind::Conical {
+            start: Point::new(geometry(0)?, geometry(1)?),
+            start_radius: geometry(2)?,
+            end: Point::new(geometry(3)?, geometry(4)?),
+            end_radius: geometry(5)?,
+        },
+        0x03 => GradientKind::Sweep {
+            center: Point::new(geometry(0)?, geometry(1)?),
+            start_angle: geometry(2)?,
+            end_angle: geometry(3)?,
+        },
+        _ => return Err(format!("unsupported {} kind", payload.name)),
+    };
+    let tile_mode = match payload.u16(2)? {
+        0x00 => TileMode::Clamp,
+        0x01 => TileMode::Repeat,
+        0x02 => TileMode::Mirror,
+        0x03 => TileMode::Decal,
+        _ => return Err(format!("unsupported {} tile mode", payload.name)),
+    };
+    let (local_matrix, mut offset) = match payload.u16(4)? {
+        0x00 => (None, 32),
+        0x01 => {
+            let m = |index: usize| payload.f32(32 + index * 4);
+            let matrix = Matrix::new_all(m(0)?, m(2)?, m(4)?, m(1)?, m(3)?, m(5)?, 0.0, 0.0, 1.0);
+            (Some(matrix), 56)
+        }
+        _ => return Err(format!("unsupported {} flags", payload.name)),
+    };
+
+    let count = payload.u16(6)? as usize;
+    if count < 2 {
+        return Err(format!("{} needs at least two stops", payload.name));
+    }
+    let mut stops = Vec::with_capacity(count);
+    let mut last = 0.0;
+    for _ in 0..count {
+        let position = payload.f32(offset)?;
+        if !(last..=1.0).contains(&position) {
+            return Err(format!("{} stop positions out of order", payload.name));
+        }
+        let [r, g, b, a] = payload.field(offset + 4)?;
+        stops.push((position, Color::from_argb(a, r, g, b)));
+        last = position;
+        offset += 8;
+    }
+
+    Ok(Gradient {
+        kind,
+        stops,
+        tile_mode,
+        local_matrix,
+    })
+}
+
+/// Big-endian fields of an extension payload.
+struct Payload<'a> {
+    name: &'static str,
+    bytes: &'a [u8],
+}
+
+impl<'a> Payload<'a> {
+    fn new(name: &'static str, bytes: &'a [u8]) -> Self {
+        Self { name, bytes }
+    }
+
+    fn field<const N: usize>(&self, offset: usize) -> Result<[u8; N], String> {
+        self.bytes
+            .get(offset..offset + N)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| format!("{} extension truncated", self.name))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, String> {
+        self.field(offset).map(u16::from_be_bytes)
+    }
+
+    fn f32(&self, offset: usize) -> Result<f32, String> {
+        self.field(offset).map(f32::from_be_bytes)
+    }
+
+    /// Blur standard deviation, which must be finite and not negative.
+    fn sigma(&self, offset: usize) -> Result<f32, String> {
+        let sigma = self.f32(offset)?;
+        if !(sigma.is_finite() && sigma >= 0.0) {
+            return Err(format!("{} sigma out of range", self.name));
+        }
+        Ok(sigma)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(id: u16, version: u16, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (FIRST_OPCODE + id).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(payload);
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+    }
+
+    fn parse_one(bytes: &[u8]) -> Result<(Option<ScriptOp>, &[u8]), String> {
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        parse(opcode, &bytes[2..])
+    }
+
+    #[test]
+    fn parses_text_extensions() {
+        let ops: Vec<_> = [
+            encode(TEXT_DRAW_MODE, 1, &[0x00, 0x02]),
+            encode(TEXT_DECORATION, 1, &[0x00, 0x03]),
+            encode(LETTER_SPACING, 1, &1.5f32.to_be_bytes()),
+            encode(
+                TEXT_WRAP,
+                1,
+                &[
+                    0x00, 0x01, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x42, 0xC8, 0x00, 0x00,
+                ],
+            ),
+            encode(TEXT_WRAP, 1, &[0; 12]),
+        ]
+        .iter()
+        .map(|bytes| {
+            let (op, tail) = parse_one(bytes).expect("parse");
+            assert!(tail.is_empty());
+            op.expect("known extension")
+        })
+        .collect();
+        assert_eq!(
+            ops,
+            vec![
+                ScriptOp::TextDrawMode(TextDrawMode::FillStroke),
+                ScriptOp::TextDecoration(TextDecoration {
+                    underline: true,
+                    strikethrough: true,
+                }),
+                ScriptOp::LetterSpacing(1.5),
+                ScriptOp::TextWrap(Some(TextWrap {
+                    max_width: 100.0,
+                    mode: TextWrapMode::Word,
+                    overflow: TextOverflow::Ellipsis,
+                    max_lines: 2,
+                })),
+                ScriptOp::TextWrap(None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_push_layer() {
+        let mut payload = 0.5f32.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        let (op, _) = parse_one(&encode(PUSH_LAYER, 1, &payload)).expect("parse");
+        assert_eq!(
+            op,
+            Some(ScriptOp::PushLayer(Layer {
+                alpha: 0.5,
+                bounds: None,
+            }))
+        );
+
+        let mut payload = 1.0f32.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[0x00, 0x01, 0x00, 0x00]);
+        for value in [1.0f32, 2.0, 30.0, 40.0] {
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        let (op, _) = parse_one(&encode(PUSH_LAYER, 1, &payload)).expect("parse");
+        assert_eq!(
+            op,
+            Some(ScriptOp::PushLayer(Layer {
+                alpha: 1.0,
+                bounds: Some(Rect::from_xywh(1.0, 2.0, 30.0, 40.0)),
+            }))
+        );
+
+        // Bounds flagged but missing, and alpha out of range.
+        assert!(parse_one(&encode(PUSH_LAYER, 1, &payload[..8])).is_err());
+        let mut payload = 1.5f32.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        assert!(parse_one(&encode(PUSH_LAYER, 1, &payload)).is_err());
+    }
+
+    #[test]
+    fn parses_blend_modes_in_skia_order() {
+        for (index, mode) in BLEND_MODES.iter().enumerate() {
+            assert_eq!(*mode as usize, index);
+            let bytes = encode(BLEND_MODE, 1, &(index as u16).to_be_bytes());
+            let (op, _) = parse_one(&bytes).expect("parse");
+            assert_eq!(op, Some(ScriptOp::BlendMode(*mode)));
+        }
+        assert!(parse_one(&encode(BLEND_MODE, 1, &[0x00, 0x1D])).is_err());
+    }
+
+    #[test]
+    fn parses_shadow_and_blurs() {
+        let mut payload = Vec::new();
+        for value in [2.0f32, 3.0, 4.0, 4.0] {
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        payload.extend_from_slice(&[0x00, 0x00, 0x00, 0x80]);
+        let (op, _) = parse_one(&encode(DROP_SHADOW, 1, &payload)).expect("parse");
+        assert_eq!(
+            op,
+            Some(ScriptOp::DropShadow(Some(DropShadow {
+                dx: 2.0,
+                dy: 3.0,
+                sigma_x: 4.0,
+                sigma_y: 4.0,
+                color: Color::from_argb(0x80, 0, 0, 0),
+            })))
+        );
+        let (op, _) = parse_one(&encode(DROP_SHADOW, 1, &[])).expect("parse");
+        assert_eq!(op, Some(ScriptOp::DropShadow(None)));
+
+        let mut payload = 5.0f32.to_be_bytes().to_vec();
+        payload.extend_from_slice(&1.0f32.to_be_bytes());
+        let blur = Some(Blur {
+            sigma_x: 5.0,
+            sigma_y: 1.0,
+        });
+        let (op, _) = parse_one(&encode(BLUR, 1, &payload)).expect("parse");
+        assert_eq!(op, Some(ScriptOp::Blur(blur)));
+        let (op, _) = parse_one(&encode(BACKDROP_BLUR, 1, &payload)).expect("parse");
+        assert_eq!(op, Some(ScriptOp::BackdropBlur(blur)));
+        let (op, _) = parse_one(&encode(BLUR, 1, &[])).expect("parse");
+        assert_eq!(op, Some(ScriptOp::Blur(None)));
+
+        let mut payload = (-1.0f32).to_be_bytes().to_vec();
+        payload.extend_from_slice(&1.0f32.to_be_bytes());
+        assert_eq!(
+            parse_one(&encode(BLUR, 1, &payload)).unwrap_err(),
+            "blur sigma out of range"
+        );
+    }
+
+    #[test]
+    fn backdrop_blur_must_precede_a_layer() {
+        let blur = Some(Blur {
+            sigma_x: 4.0,
+            sigma_y: 4.0,
+        });
+        let layer = ScriptOp::PushLayer(Layer {
+            alpha: 1.0,
+            bounds: None,
+        });
+        let rect = ScriptOp::DrawRect {
+            width: 10.0,
+            height: 10.0,
+            flag: 0x01,
+        };
+        assert!(
+            check_backdrop_blur(&[
+                ScriptOp::BackdropBlur(blur),
+                ScriptOp::Blur(None),
+                layer.clone(),
+                rect.clone(),
+                ScriptOp::PopState,
+                rect.clone(),
+            ])
+            .is_ok()
+        );
+        assert!(check_backdrop_blur(&[ScriptOp::BackdropBlur(None), rect.clone()]).is_ok());
+
+        let error = Err("backdrop_blur must be followed by push_layer".to_string());
+        assert_eq!(
+            check_backdrop_blur(&[ScriptOp::BackdropBlur(blur), rect, layer]),
+            error
+        );
+        assert_eq!(check_backdrop_blur(&[ScriptOp::BackdropBlur(blur)]), error);
+    }
+
+    fn gradient_payload(kind: u16, flags: u16, geometry: &[f32], stops: &[(f32, u32)]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for field in [kind, 0x02, flags, stops.len() as u16] {
+            payload.extend_from_slice(&field.to_be_bytes());
+        }
+        for value in geometry {
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        for (position, rgba) in stops {
+            payload.extend_from_slice(&position.to_be_bytes());
+            payload.extend_from_slice(&rgba.to_be_bytes());
+        }
+        payload
+    }
+
+    #[test]
+    fn parses_gradients() {
+        let stops = [(0.0, 0xFF0000FF), (0.25, 0x00FF00FF), (1.0, 0x0000FF80)];
+        let colors = vec![
+            (0.0, Color::RED),
+            (0.25, Color::GREEN),
+            (1.0, Color::from_argb(0x80, 0, 0, 255)),
+        ];
+        let payload = gradient_payload(0x03, 0x00, &[10.0, 20.0, 0.0, 90.0, 0.0, 0.0], &stops);
+        let (op, tail) = parse_one(&encode(FILL_GRADIENT, 1, &payload)).expect("parse");
+        assert!(tail.is_empty());
+        assert_eq!(
+            op,
+            Some(ScriptOp::FillGradient(Gradient {
+                kind: GradientKind::Sweep {
+                    center: Point::new(10.0, 20.0),
+                    start_angle: 0.0,
+                    end_angle: 90.0,
+                },
+                stops: colors.clone(),
+                tile_mode: TileMode::Mirror,
+                local_matrix: None,
+            }))
+        );
+
+        let geometry = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 2.0, 0.0, 0.0, 3.0, 7.0, 8.0];
+        let payload = gradient_payload(0x02, 0x01, &geometry, &stops);
+        let (op, _) = parse_one(&encode(STROKE_GRADIENT, 1, &payload)).expect("parse");
+        assert_eq!(
+            op,
+            Some(ScriptOp::StrokeGradient(Gradient {
+                kind: GradientKind::Conical {
+                    start: Point::new(1.0, 2.0),
+                    start_radius: 3.0,
+                    end: Point::new(4.0, 5.0),
+                    end_radius: 6.0,
+                },
+                stops: colors,
+                tile_mode: TileMode::Mirror,
+                local_matrix: Some(Matrix::new_all(2.0, 0.0, 7.0, 0.0, 3.0, 8.0, 0.0, 0.0, 1.0)),
+            }))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_gradients() {
+        let geometry = [0.0; 6];
+        let payload = gradient_payload(0x00, 0x00, &geometry, &[(0.0, 0xFF)]);
+        assert_eq!(
+            parse_one(&encode(FILL_GRADIENT, 1, &payload)).unwrap_err(),
+            "fill_gradient needs at least two stops"
+        );
+        let payload = gradient_payload(0x00, 0x00, &geometry, &[(0.5, 0xFF), (0.25, 0xFF)]);
+        assert_eq!(
+            parse_one(&encode(FILL_GRADIENT, 1, &payload)).unwrap_err(),
+            "fill_gradient stop positions out of order"
+        );
+        let payload = gradient_payload(0x04, 0x00, &geometry, &[(0.0, 0xFF), (1.0, 0xFF)]);
+        assert!(parse_one(&encode(STROKE_GRADIENT, 1, &payload)).is_err());
+        // The stop count promises more stops than the payload holds.
+        let mut payload = gradient_payload(0x00, 0x00, &geometry, &[(0.0, 0xFF), (1.0, 0xFF)]);
+        payload[7] = 3;
+        assert_eq!(
+            parse_one(&encode(FILL_GRADIENT, 1, &payload)).unwrap_err(),
+            "fill_gradient extension truncated"
+        );
+    }
+
+    #[test]
+    fn skips_unknown_extensions_and_versions() {
+        let mut bytes = encode(0xABC, 1, &[1, 2, 3, 4, 5]);
+        bytes.extend(encode(TEXT_DRAW_MODE, 2, &[0x00, 0x01, 0x00, 0x00]));
+        let (op, tail) = parse_one(&bytes).expect("parse");
+        assert_eq!(op, None);
+        assert_eq!(tail.len(), 12);
+        let (op, tail) = parse_one(tail).expect("parse");
+        assert_eq!(op, None);
+        assert!(tail.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_and_invalid_payloads() {
+        let bytes = encode(TEXT_DRAW_MODE, 1, &[0x00, 0x03]);
+        assert!(parse_one(&bytes).is_err());
+        let bytes = encode(TEXT_DECORATION, 1, &[0x00, 0x04]);
+        assert!(parse_one(&bytes).is_err());
+        let bytes = encode(TEXT_WRAP, 1, &[0x00, 0x01]);
+        assert_eq!(
+            parse_one(&bytes).unwrap_err(),
+            "text_wrap extension truncated"
+        );
+        let mut bytes = encode(LETTER_SPACING, 1, &[0; 4]);
+        bytes.truncate(8);
+        assert!(parse_one(&bytes).is_err());
+    }
+}
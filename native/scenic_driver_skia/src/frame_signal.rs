@@ -0,0 +1,135 @@
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Wakes a render loop when a new frame is needed.
+///
+/// Threads block on the condvar, or poll the eventfd alongside other file descriptors
+/// (the DRM loop waits on it together with its input devices).
+pub struct FrameSignal {
+    pending: Mutex<bool>,
+    condvar: Condvar,
+    event_fd: OwnedFd,
+}
+
+impl FrameSignal {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            pending: Mutex::new(false),
+            condvar: Condvar::new(),
+            event_fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Requests a frame and wakes any waiting render loop.
+    pub fn notify(&self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            *pending = true;
+        }
+        self.condvar.notify_all();
+        let value: u64 = 1;
+        unsafe {
+            libc::write(
+                self.event_fd.as_raw_fd(),
+                (&value as *const u64).cast(),
+                std::mem::size_of::<u64>(),
+            );
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.lock().map(|pending| *pending).unwrap_or(false)
+    }
+
+    /// Consumes a pending frame request.
+    pub fn take(&self) -> bool {
+        let was_pending = self
+            .pending
+            .lock()
+            .map(|mut pending| std::mem::replace(&mut *pending, false))
+            .unwrap_or(false);
+        let mut value: u64 = 0;
+        unsafe {
+            libc::read(
+                self.event_fd.as_raw_fd(),
+                (&mut value as *mut u64).cast(),
+                std::mem::size_of::<u64>(),
+            );
+        }
+        was_pending
+    }
+
+    /// Blocks until a frame is requested or `deadline` passes, without consuming the
+    /// request. Returns whether a frame is pending.
+    pub fn wait_until(&self, deadline: Option<Instant>) -> bool {
+        let Ok(mut pending) = self.pending.lock() else {
+            return false;
+        };
+        while !*pending {
+            match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    if timeout.is_zero() {
+                        break;
+                    }
+                    pending = match self.condvar.wait_timeout(pending, timeout) {
+                        Ok((guard, _)) => guard,
+                        Err(_) => return false,
+                    };
+                }
+                None => {
+                    pending = match self.condvar.wait(pending) {
+                        Ok(guard) => guard,
+                        Err(_) => return false,
+                    };
+                }
+            }
+        }
+        *pending
+    }
+
+    pub fn raw_fd(&self) -> RawFd {
+        self.event_fd.as_raw_fd()
+    }
+}
+
+/// Minimum time between frames for a `max_fps` limit; `None` or 0 means unlimited.
+pub fn min_frame_interval(max_fps: Option<u32>) -> Option<Duration> {
+    max_fps
+        .filter(|fps| *fps > 0)
+        .map(|fps| Duration::from_nanos(1_000_000_000 / u64::from(fps)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notify_wakes_waiter_and_take_consumes() {
+        let signal = std::sync::Arc::new(FrameSignal::new().expect("eventfd"));
+        let waiter = {
+            let signal = std::sync::Arc::clone(&signal);
+            std::thread::spawn(move || signal.wait_until(None))
+        };
+        signal.notify();
+        assert!(waiter.join().expect("waiter panicked"));
+        assert!(signal.take());
+        assert!(!signal.take());
+        assert!(!signal.wait_until(Some(Instant::now() + Duration::from_millis(5))));
+    }
+
+    #[test]
+    fn max_fps_maps_to_frame_interval() {
+        assert_eq!(min_frame_interval(None), None);
+        assert_eq!(min_frame_interval(Some(0)), None);
+        assert_eq!(
+            min_frame_interval(Some(50)),
+            Some(Duration::from_millis(20))
+        );
+    }
+}
@@ -1,13 +1,19 @@
+mod assets;
 mod backend;
 mod cursor;
+mod damage;
 mod drm_backend;
 mod drm_input;
+mod extension;
+mod frame_signal;
 mod input;
 mod input_translate;
+mod presentation;
 mod raster_backend;
 mod renderer;
+mod text;
 
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::{
     Arc, Mutex,
     atomic::{AtomicBool, AtomicU32, Ordering},
@@ -16,12 +22,17 @@ use std::sync::{
 use std::thread;
 use std::time::Duration;
 
+use arc_swap::ArcSwap;
+use assets::{AssetCache, FontFallback};
 use backend::UserEvent;
 use cursor::CursorState;
+use frame_signal::FrameSignal;
 use input::{InputEvent, InputQueue};
-use renderer::{RenderState, ScriptOp};
+use presentation::PresentationNotifier;
+use renderer::{RenderState, Scene, ScriptOp, SharedRenderState};
 use rustler::{Binary, Env, OwnedBinary, ResourceArc, Term};
-use skia_safe::ClipOp;
+use skia_safe::{ClipOp, Image, Typeface};
+use text::{FontRendering, SharedTextEngine};
 
 enum StopSignal {
     Wayland(winit::event_loop::EventLoopProxy<UserEvent>),
@@ -31,16 +42,111 @@ enum StopSignal {
 
 struct DriverHandle {
     stop: StopSignal,
-    render_state: Arc<Mutex<RenderState>>,
+    /// Scene being edited by NIFs; its render state is published to `published_state`
+    /// after each update.
+    scene: Scene,
+    published_state: SharedRenderState,
+    /// Set between `begin_update` and `commit_update`; defers publishing and assets.
+    /// Updates don't nest: the driver process is the only writer and always pairs
+    /// `begin_update` with `commit_update`. `stop` drops an update left open.
+    in_update: bool,
+    pending_assets: Vec<AssetUpdate>,
     input_events: Arc<Mutex<InputQueue>>,
     input_mask: Arc<AtomicU32>,
     raster_frame: Option<Arc<Mutex<Option<RasterFrame>>>>,
-    dirty: Option<Arc<AtomicBool>>,
+    frame_signal: Option<Arc<FrameSignal>>,
+    presentation: Arc<PresentationNotifier>,
+    /// Images, streams and fonts for this driver; cleared on `stop`.
+    assets: Arc<AssetCache>,
+    /// Engine `measure_text` lays out with; replaced on `stop` to free its typefaces.
+    measure_engine: Arc<SharedTextEngine>,
     running: Arc<AtomicBool>,
     cursor_state: Option<Arc<Mutex<CursorState>>>,
     thread: Option<thread::JoinHandle<()>>,
 }
 
+/// Asset change held back until the surrounding update is committed.
+enum AssetUpdate {
+    StaticImage(String, Image),
+    RemoveStaticImage(String),
+    Font(String, Typeface),
+    RemoveFont(String),
+    StreamImage(String, Image),
+    RemoveStream(String),
+}
+
+impl AssetUpdate {
+    fn apply(self, assets: &AssetCache) -> Result<(), String> {
+        match self {
+            AssetUpdate::StaticImage(id, image) => assets.insert_static_image(&id, image),
+            AssetUpdate::RemoveStaticImage(id) => {
+                assets.remove_static_image(&id);
+            }
+            AssetUpdate::Font(id, typeface) => assets.insert_typeface(&id, typeface)?,
+            AssetUpdate::RemoveFont(id) => {
+                assets.remove_font(&id)?;
+            }
+            AssetUpdate::StreamImage(id, image) => assets.insert_stream_image(&id, image),
+            AssetUpdate::RemoveStream(id) => assets.remove_stream_image(&id),
+        }
+        Ok(())
+    }
+
+    /// Whether this staged update loads (`Some(true)`) or removes (`Some(false)`) the
+    /// asset `kind`/`id`.
+    fn loads(&self, kind: &str, id: &str) -> Option<bool> {
+        let (update_kind, update_id, loads) = match self {
+            AssetUpdate::StaticImage(id, _) => ("image", id, true),
+            AssetUpdate::RemoveStaticImage(id) => ("image", id, false),
+            AssetUpdate::Font(id, _) => ("font", id, true),
+            AssetUpdate::RemoveFont(id) => ("font", id, false),
+            AssetUpdate::StreamImage(id, _) => ("stream", id, true),
+            AssetUpdate::RemoveStream(id) => ("stream", id, false),
+        };
+        (update_kind == kind && update_id == id).then_some(loads)
+    }
+
+    /// Id of the static image this update loads or removes.
+    fn static_image(&self) -> Option<&str> {
+        match self {
+            AssetUpdate::StaticImage(id, _) | AssetUpdate::RemoveStaticImage(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(rustler::NifMap)]
+struct CacheStats {
+    images: u64,
+    image_bytes: u64,
+    streams: u64,
+    stream_bytes: u64,
+    fonts: u64,
+    budget_bytes: Option<u64>,
+}
+
+/// `text` options passed to `start`: hinting `"none" | "slight" | "normal" | "full"` and
+/// edging `"alias" | "antialias" | "subpixel_antialias"`.
+#[derive(rustler::NifMap)]
+struct FontRenderingOptions {
+    hinting: String,
+    subpixel: bool,
+    edging: String,
+    embolden: bool,
+}
+
+/// Result of `measure_text`. Lengths are in pixels relative to the start of the first
+/// line's alphabetic baseline; `ascent` is negative, as in Skia.
+#[derive(rustler::NifMap)]
+struct TextMetrics {
+    width: f64,
+    bounds: (f64, f64, f64, f64),
+    ascent: f64,
+    descent: f64,
+    leading: f64,
+    line_widths: Vec<f64>,
+}
+
 struct RendererResource {
     handle: Mutex<DriverHandle>,
 }
@@ -56,6 +162,7 @@ pub(crate) struct RasterFrame {
 const ROOT_ID: &str = "_root_";
 
 #[rustler::nif(schedule = "DirtyIo")]
+#[allow(clippy::too_many_arguments)]
 pub fn start(
     backend: Option<String>,
     viewport_size: Option<(u32, u32)>,
@@ -64,21 +171,45 @@ pub fn start(
     drm_card: Option<String>,
     drm_hw_cursor: bool,
     drm_input_log: bool,
+    max_fps: Option<u32>,
+    shared_fonts: bool,
+    image_cache_bytes: Option<u64>,
+    font_rendering: Option<FontRenderingOptions>,
 ) -> Result<ResourceArc<RendererResource>, String> {
+    let font_rendering = match font_rendering {
+        Some(options) => FontRendering::parse(
+            &options.hinting,
+            options.subpixel,
+            &options.edging,
+            options.embolden,
+        )?,
+        None => FontRendering::default(),
+    };
     let backend = backend
         .map(|b| b.to_lowercase())
         .unwrap_or_else(|| String::from("wayland"));
+    let min_frame_interval = frame_signal::min_frame_interval(max_fps);
 
     let thread_name = format!("scenic-driver-{backend}");
-    let render_state = Arc::new(Mutex::new(RenderState::default()));
+    let published_state: SharedRenderState =
+        Arc::new(ArcSwap::from_pointee(RenderState::default()));
     let input_events = Arc::new(Mutex::new(InputQueue::new()));
     let input_mask = Arc::new(AtomicU32::new(0));
     let running = Arc::new(AtomicBool::new(true));
+    let presentation = Arc::new(PresentationNotifier::default());
+    let presentation_for_thread = Arc::clone(&presentation);
+    let assets = Arc::new(
+        AssetCache::new(shared_fonts, image_cache_bytes.map(|bytes| bytes as usize))
+            .with_font_rendering(font_rendering),
+    );
+    let assets_for_thread = Arc::clone(&assets);
     let handle = if backend == "drm" {
         let stop = Arc::new(AtomicBool::new(false));
-        let dirty = Arc::new(AtomicBool::new(false));
-        let state_for_thread = Arc::clone(&render_state);
-        let dirty_for_thread = Arc::clone(&dirty);
+        let frame_signal = Arc::new(
+            FrameSignal::new().map_err(|err| format!("failed to create frame signal: {err}"))?,
+        );
+        let state_for_thread = Arc::clone(&published_state);
+        let signal_for_thread = Arc::clone(&frame_signal);
         let stop_for_thread = Arc::clone(&stop);
         let input_for_thread = Arc::clone(&input_mask);
         let input_events_for_thread = Arc::clone(&input_events);
@@ -91,7 +222,7 @@ pub fn start(
             .spawn(move || {
                 drm_backend::run(
                     stop_for_thread,
-                    dirty_for_thread,
+                    signal_for_thread,
                     state_for_thread,
                     input_for_thread,
                     input_events_for_thread,
@@ -101,26 +232,37 @@ pub fn start(
                         card_path: drm_card,
                         hw_cursor: drm_hw_cursor,
                         input_log: drm_input_log,
+                        min_frame_interval,
+                        presentation: presentation_for_thread,
+                        assets: assets_for_thread,
                     },
                 )
             })
             .map_err(|err| format!("failed to spawn renderer thread: {err}"))?;
         DriverHandle {
             stop: StopSignal::Drm(stop),
-            render_state,
+            scene: Scene::default(),
+            published_state,
+            in_update: false,
+            pending_assets: Vec::new(),
             input_events,
             input_mask,
             raster_frame: None,
-            dirty: Some(dirty),
+            frame_signal: Some(frame_signal),
+            presentation,
+            assets,
+            measure_engine: Arc::default(),
             running,
             cursor_state: Some(cursor_state),
             thread: Some(thread),
         }
     } else if backend == "raster" {
         let stop = Arc::new(AtomicBool::new(false));
-        let dirty = Arc::new(AtomicBool::new(false));
-        let state_for_thread = Arc::clone(&render_state);
-        let dirty_for_thread = Arc::clone(&dirty);
+        let frame_signal = Arc::new(
+            FrameSignal::new().map_err(|err| format!("failed to create frame signal: {err}"))?,
+        );
+        let state_for_thread = Arc::clone(&published_state);
+        let signal_for_thread = Arc::clone(&frame_signal);
         let stop_for_thread = Arc::clone(&stop);
         let raster_frame = Arc::new(Mutex::new(None));
         let frame_for_thread = Arc::clone(&raster_frame);
@@ -131,21 +273,30 @@ pub fn start(
             .spawn(move || {
                 raster_backend::run(
                     stop_for_thread,
-                    dirty_for_thread,
+                    signal_for_thread,
                     state_for_thread,
                     frame_for_thread,
                     input_for_thread,
                     requested_size,
+                    min_frame_interval,
+                    presentation_for_thread,
+                    assets_for_thread,
                 )
             })
             .map_err(|err| format!("failed to spawn renderer thread: {err}"))?;
         DriverHandle {
             stop: StopSignal::Raster(stop),
-            render_state,
+            scene: Scene::default(),
+            published_state,
+            in_update: false,
+            pending_assets: Vec::new(),
             input_events,
             input_mask,
             raster_frame: Some(raster_frame),
-            dirty: Some(dirty),
+            frame_signal: Some(frame_signal),
+            presentation,
+            assets,
+            measure_engine: Arc::default(),
             running,
             cursor_state: None,
             thread: Some(thread),
@@ -153,7 +304,7 @@ pub fn start(
     } else {
         let (proxy_tx, proxy_rx) = mpsc::channel();
         let running_for_thread = Arc::clone(&running);
-        let state_for_thread = Arc::clone(&render_state);
+        let state_for_thread = Arc::clone(&published_state);
         let input_for_thread = Arc::clone(&input_mask);
         let input_events_for_thread = Arc::clone(&input_events);
         let requested_size = viewport_size;
@@ -166,10 +317,13 @@ pub fn start(
                     state_for_thread,
                     input_for_thread,
                     input_events_for_thread,
+                    presentation_for_thread,
+                    assets_for_thread,
                     backend::WaylandWindowConfig {
                         requested_size,
                         window_title,
                         window_resizeable,
+                        min_frame_interval,
                     },
                 )
             })
@@ -179,11 +333,17 @@ pub fn start(
             .map_err(|_| "renderer did not initialize in time".to_string())?;
         DriverHandle {
             stop: StopSignal::Wayland(proxy),
-            render_state,
+            scene: Scene::default(),
+            published_state,
+            in_update: false,
+            pending_assets: Vec::new(),
             input_events,
             input_mask,
             raster_frame: None,
-            dirty: None,
+            frame_signal: None,
+            presentation,
+            assets,
+            measure_engine: Arc::default(),
             running,
             cursor_state: None,
             thread: Some(thread),
@@ -212,33 +372,106 @@ fn signal_redraw(handle: &mut DriverHandle) -> Result<(), String> {
             .send_event(UserEvent::Redraw)
             .map_err(|err| format!("failed to signal renderer: {err}")),
         StopSignal::Drm(_) | StopSignal::Raster(_) => {
-            if let Some(dirty) = &handle.dirty {
-                dirty.store(true, Ordering::Relaxed);
+            if let Some(frame_signal) = &handle.frame_signal {
+                frame_signal.notify();
             }
             Ok(())
         }
     }
 }
 
-fn update_render_state<F>(renderer: &RendererResource, update: F) -> Result<(), String>
+fn update_scene<F>(renderer: &RendererResource, update: F) -> Result<(), String>
 where
-    F: FnOnce(&mut RenderState) -> Result<(), String>,
+    F: FnOnce(&mut Scene) -> Result<(), String>,
 {
     with_handle(renderer, |handle| {
-        let mut render_state = handle
-            .render_state
-            .lock()
-            .map_err(|_| "render state lock poisoned".to_string())?;
-        update(&mut render_state)?;
-        drop(render_state);
+        update(&mut handle.scene)?;
+        if handle.in_update {
+            return Ok(());
+        }
+        publish_render_state(handle)
+    })
+}
+
+fn publish_render_state(handle: &mut DriverHandle) -> Result<(), String> {
+    handle
+        .published_state
+        .store(Arc::new(handle.scene.state.clone()));
+    trim_assets(handle);
+    signal_redraw(handle)
+}
+
+/// Evicts images and streams the scene no longer draws once the cache is over budget.
+fn trim_assets(handle: &DriverHandle) {
+    if handle.assets.over_budget() {
+        handle.assets.trim(handle.scene.media_refs());
+    }
+}
+
+fn update_asset(renderer: &RendererResource, update: AssetUpdate) -> Result<(), String> {
+    with_handle(renderer, |handle| {
+        if handle.in_update {
+            handle.pending_assets.push(update);
+            return Ok(());
+        }
+        let image = update.static_image().map(str::to_string);
+        update.apply(&handle.assets)?;
+        if let Some(id) = image {
+            // Only the scripts drawing the image need to be re-recorded and redrawn.
+            handle.scene.image_changed(&id);
+            return publish_render_state(handle);
+        }
+        trim_assets(handle);
         signal_redraw(handle)
     })
 }
 
+fn begin_update_inner(renderer: &RendererResource) -> Result<(), String> {
+    with_handle(renderer, |handle| {
+        if handle.in_update {
+            return Err("update already in progress".to_string());
+        }
+        handle.in_update = true;
+        Ok(())
+    })
+}
+
+fn commit_update_inner(renderer: &RendererResource) -> Result<(), String> {
+    with_handle(renderer, |handle| {
+        handle.in_update = false;
+        let pending = std::mem::take(&mut handle.pending_assets);
+        let applied = pending.into_iter().try_for_each(|update| {
+            if let Some(id) = update.static_image() {
+                handle.scene.image_changed(id);
+            }
+            update.apply(&handle.assets)
+        });
+        // Publish even if an asset failed so the scene never stays frozen.
+        publish_render_state(handle).and(applied)
+    })
+}
+
+/// Holds back scene and asset changes until `commit_update`, so the render thread
+/// never draws a partially applied update. Fails if an update is already open.
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn begin_update(renderer: ResourceArc<RendererResource>) -> Result<(), String> {
+    begin_update_inner(&renderer)
+}
+
+/// Applies everything staged since `begin_update` and publishes it as one frame.
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn commit_update(renderer: ResourceArc<RendererResource>) -> Result<(), String> {
+    commit_update_inner(&renderer)
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 pub fn stop(renderer: ResourceArc<RendererResource>) -> Result<(), String> {
     with_handle(&renderer, |handle| {
         if !handle.running.load(Ordering::Relaxed) {
+            handle.in_update = false;
+            handle.pending_assets.clear();
+            handle.assets.clear();
+            handle.measure_engine = Arc::default();
             return Ok(());
         }
 
@@ -246,12 +479,11 @@ pub fn stop(renderer: ResourceArc<RendererResource>) -> Result<(), String> {
             StopSignal::Wayland(proxy) => proxy
                 .send_event(UserEvent::Stop)
                 .map_err(|err| format!("failed to signal renderer: {err}")),
-            StopSignal::Drm(stop) => {
-                stop.store(true, Ordering::Relaxed);
-                Ok(())
-            }
-            StopSignal::Raster(stop) => {
+            StopSignal::Drm(stop) | StopSignal::Raster(stop) => {
                 stop.store(true, Ordering::Relaxed);
+                if let Some(frame_signal) = &handle.frame_signal {
+                    frame_signal.notify();
+                }
                 Ok(())
             }
         };
@@ -263,6 +495,10 @@ pub fn stop(renderer: ResourceArc<RendererResource>) -> Result<(), String> {
                 .map_err(|_| "renderer thread panicked".to_string()),
             None => Ok(()),
         };
+        handle.in_update = false;
+        handle.pending_assets.clear();
+        handle.assets.clear();
+        handle.measure_engine = Arc::default();
 
         signal_result.and(join_result)
     })
@@ -270,9 +506,8 @@ pub fn stop(renderer: ResourceArc<RendererResource>) -> Result<(), String> {
 
 #[rustler::nif(schedule = "DirtyIo")]
 pub fn reset_scene(renderer: ResourceArc<RendererResource>) -> Result<(), String> {
-    update_render_state(&renderer, |state| {
-        state.scripts = HashMap::new();
-        state.root_id = None;
+    update_scene(&renderer, |state| {
+        state.clear_scripts();
         Ok(())
     })
 }
@@ -282,8 +517,8 @@ pub fn set_clear_color(
     renderer: ResourceArc<RendererResource>,
     color: (u8, u8, u8, u8),
 ) -> Result<(), String> {
-    update_render_state(&renderer, |state| {
-        state.clear_color = skia_safe::Color::from_argb(color.3, color.0, color.1, color.2);
+    update_scene(&renderer, |state| {
+        state.state.clear_color = skia_safe::Color::from_argb(color.3, color.0, color.1, color.2);
         Ok(())
     })
 }
@@ -293,7 +528,7 @@ pub fn submit_script(
     renderer: ResourceArc<RendererResource>,
     script: rustler::Binary,
 ) -> Result<(), String> {
-    update_render_state(&renderer, |state| {
+    update_scene(&renderer, |state| {
         let ops = parse_script(script.as_slice())?;
         set_script(state, ROOT_ID.to_string(), ops);
         Ok(())
@@ -306,7 +541,7 @@ pub fn submit_script_with_id(
     id: String,
     script: rustler::Binary,
 ) -> Result<(), String> {
-    update_render_state(&renderer, |state| {
+    update_scene(&renderer, |state| {
         let ops = parse_script(script.as_slice())?;
         set_script(state, id.clone(), ops);
         Ok(())
@@ -318,7 +553,7 @@ pub fn submit_scripts(
     renderer: ResourceArc<RendererResource>,
     scripts: Vec<(String, rustler::Binary)>,
 ) -> Result<(), String> {
-    update_render_state(&renderer, |state| {
+    update_scene(&renderer, |state| {
         let mut staged: Vec<(String, Vec<ScriptOp>)> = Vec::with_capacity(scripts.len());
         for (id, script) in scripts.iter() {
             let ops = parse_script(script.as_slice())?;
@@ -338,18 +573,26 @@ pub fn put_static_image(
     data: rustler::Binary,
 ) -> Result<(), String> {
     let image = renderer::decode_texture_image("file", 0, 0, data.as_slice())?;
-    renderer::insert_static_image(&id, image);
-    with_handle(&renderer, signal_redraw)
+    update_asset(&renderer, AssetUpdate::StaticImage(id, image))
 }
 
+/// Loads face `face_index` of a font file or collection under `id`, instanced at the
+/// variation coordinates in `axes`, e.g. `[{"wght", 700.0}]`. Each face and instance
+/// needs its own id.
 #[rustler::nif(schedule = "DirtyIo")]
 pub fn put_font(
     renderer: ResourceArc<RendererResource>,
     id: String,
     data: rustler::Binary,
+    face_index: u32,
+    axes: Vec<(String, f64)>,
 ) -> Result<(), String> {
-    renderer::insert_font(&id, data.as_slice())?;
-    with_handle(&renderer, signal_redraw)
+    let axes: Vec<(String, f32)> = axes
+        .into_iter()
+        .map(|(tag, value)| (tag, value as f32))
+        .collect();
+    let typeface = text::decode_font(data.as_slice(), face_index as usize, &axes)?;
+    update_asset(&renderer, AssetUpdate::Font(id, typeface))
 }
 
 #[rustler::nif(schedule = "DirtyIo")]
@@ -362,8 +605,7 @@ pub fn put_stream_texture(
     data: rustler::Binary,
 ) -> Result<(), String> {
     let image = renderer::decode_texture_image(&format, width, height, data.as_slice())?;
-    renderer::insert_stream_image(&id, image);
-    with_handle(&renderer, signal_redraw)
+    update_asset(&renderer, AssetUpdate::StreamImage(id, image))
 }
 
 #[rustler::nif(schedule = "DirtyIo")]
@@ -371,16 +613,138 @@ pub fn del_stream_texture(
     renderer: ResourceArc<RendererResource>,
     id: String,
 ) -> Result<(), String> {
-    renderer::remove_stream_image(&id);
-    with_handle(&renderer, signal_redraw)
+    update_asset(&renderer, AssetUpdate::RemoveStream(id))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn del_static_image(renderer: ResourceArc<RendererResource>, id: String) -> Result<(), String> {
+    update_asset(&renderer, AssetUpdate::RemoveStaticImage(id))
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn del_font(renderer: ResourceArc<RendererResource>, id: String) -> Result<(), String> {
+    update_asset(&renderer, AssetUpdate::RemoveFont(id))
+}
+
+/// Sets the font ids consulted, in order, for characters missing from the selected font.
+/// With `system_fonts`, the system fonts are tried after them.
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn set_font_fallback(
+    renderer: ResourceArc<RendererResource>,
+    font_ids: Vec<String>,
+    system_fonts: bool,
+) -> Result<(), String> {
+    with_handle(&renderer, |handle| {
+        handle.assets.set_font_fallback(FontFallback {
+            font_ids,
+            system_fonts,
+        });
+        signal_redraw(handle)
+    })
+}
+
+/// Whether an `"image"`, `"stream"` or `"font"` asset is loaded, counting updates staged
+/// since `begin_update`. Lets the driver re-upload assets that were evicted.
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn asset_loaded(
+    renderer: ResourceArc<RendererResource>,
+    kind: String,
+    id: String,
+) -> Result<bool, String> {
+    with_handle(&renderer, |handle| {
+        if let Some(loads) = handle
+            .pending_assets
+            .iter()
+            .rev()
+            .find_map(|update| update.loads(&kind, &id))
+        {
+            return Ok(loads);
+        }
+        match kind.as_str() {
+            "image" => Ok(handle.assets.has_static_image(&id)),
+            "stream" => Ok(handle.assets.has_stream_image(&id)),
+            "font" => Ok(handle.assets.has_font(&id)),
+            _ => Err(format!("unsupported asset kind: {kind}")),
+        }
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn cache_stats(renderer: ResourceArc<RendererResource>) -> Result<CacheStats, String> {
+    with_handle(&renderer, |handle| {
+        let stats = handle.assets.stats();
+        Ok(CacheStats {
+            images: stats.images as u64,
+            image_bytes: stats.image_bytes as u64,
+            streams: stats.streams as u64,
+            stream_bytes: stats.stream_bytes as u64,
+            fonts: stats.fonts as u64,
+            budget_bytes: stats.budget_bytes.map(|bytes| bytes as u64),
+        })
+    })
+}
+
+/// Lays out `text` the way `DrawText` does, left aligned, and reports its metrics.
+/// `font_id` of `nil` measures with the default font. Runs on a dirty I/O scheduler
+/// since it may wait on the asset cache locks.
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn measure_text(
+    renderer: ResourceArc<RendererResource>,
+    font_id: Option<String>,
+    size: f64,
+    text: String,
+) -> Result<TextMetrics, String> {
+    let (assets, engine) = with_handle(&renderer, |handle| {
+        Ok((handle.assets.clone(), handle.measure_engine.clone()))
+    })?;
+    let layout = engine
+        .with(|engine| {
+            // Drops typefaces of fonts deleted since the last measurement.
+            engine.begin_frame(assets.font_generation());
+            engine.layout(
+                &assets,
+                font_id.as_deref(),
+                size as f32,
+                &text,
+                renderer::TextAlign::Left,
+                0.0,
+                None,
+            )
+        })?
+        .ok_or_else(|| match &font_id {
+            Some(font_id) => format!("font not loaded: {font_id}"),
+            None => "no default font".to_string(),
+        })?;
+    let bounds = if layout.bounds.is_empty() {
+        (0.0, 0.0, 0.0, 0.0)
+    } else {
+        (
+            layout.bounds.left as f64,
+            layout.bounds.top as f64,
+            layout.bounds.right as f64,
+            layout.bounds.bottom as f64,
+        )
+    };
+    Ok(TextMetrics {
+        width: layout.width as f64,
+        bounds,
+        ascent: layout.ascent as f64,
+        descent: layout.descent as f64,
+        leading: layout.leading as f64,
+        line_widths: layout
+            .line_widths
+            .iter()
+            .map(|width| *width as f64)
+            .collect(),
+    })
 }
 
 #[rustler::nif(schedule = "DirtyIo")]
 pub fn del_script(renderer: ResourceArc<RendererResource>, id: String) -> Result<(), String> {
-    update_render_state(&renderer, |state| {
-        state.scripts.remove(&id);
-        if state.root_id.as_deref() == Some(id.as_str()) {
-            state.root_id = None;
+    update_scene(&renderer, |state| {
+        state.remove_script(&id);
+        if state.state.root_id.as_deref() == Some(id.as_str()) {
+            state.state.root_id = None;
         }
         Ok(())
     })
@@ -389,11 +753,26 @@ pub fn del_script(renderer: ResourceArc<RendererResource>, id: String) -> Result
 #[rustler::nif(schedule = "DirtyIo")]
 pub fn script_count(renderer: ResourceArc<RendererResource>) -> Result<u64, String> {
     with_handle(&renderer, |handle| {
-        let render_state = handle
-            .render_state
-            .lock()
-            .map_err(|_| "render state lock poisoned".to_string())?;
-        Ok(render_state.scripts.len() as u64)
+        Ok(handle.scene.state.scripts.len() as u64)
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn script_dependencies(
+    renderer: ResourceArc<RendererResource>,
+    id: String,
+) -> Result<(Vec<String>, Vec<String>), String> {
+    with_handle(&renderer, |handle| {
+        let scene = &handle.scene;
+        let sorted = |ids: Option<&HashSet<String>>| {
+            let mut ids: Vec<String> = ids.into_iter().flatten().cloned().collect();
+            ids.sort();
+            ids
+        };
+        Ok((
+            sorted(scene.dependencies.get(&id)),
+            sorted(scene.dependents.get(&id)),
+        ))
     })
 }
 
@@ -446,8 +825,8 @@ fn set_cursor_visible(renderer: &RendererResource, visible: bool) -> Result<(),
             cursor.visible = visible;
         }
 
-        if let Some(dirty) = &handle.dirty {
-            dirty.store(true, Ordering::Relaxed);
+        if let Some(frame_signal) = &handle.frame_signal {
+            frame_signal.notify();
         }
 
         Ok(())
@@ -470,6 +849,17 @@ pub fn set_input_target(
     })
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+pub fn set_frame_target(
+    renderer: ResourceArc<RendererResource>,
+    pid: Option<rustler::LocalPid>,
+) -> Result<(), String> {
+    with_handle(&renderer, |handle| {
+        handle.presentation.set_target(pid);
+        Ok(())
+    })
+}
+
 #[rustler::nif(schedule = "DirtyIo")]
 pub fn drain_input_events(
     renderer: ResourceArc<RendererResource>,
@@ -487,11 +877,11 @@ fn drain_input_events_inner(renderer: &RendererResource) -> Result<Vec<InputEven
     })
 }
 
-fn set_script(state: &mut RenderState, id: String, ops: Vec<ScriptOp>) {
-    state.scripts.insert(id.clone(), ops);
+fn set_script(scene: &mut Scene, id: String, ops: Vec<ScriptOp>) {
     if id == ROOT_ID {
-        state.root_id = Some(id);
+        scene.state.root_id = Some(id.clone());
     }
+    scene.insert_script(id, ops);
 }
 
 fn is_known_opcode(opcode: u16) -> bool {
@@ -554,6 +944,7 @@ fn is_known_opcode(opcode: u16) -> bool {
             | 0x91
             | 0x92
             | 0x93
+            | extension::FIRST_OPCODE..=u16::MAX
     )
 }
 
@@ -2125,11 +2516,17 @@ fn parse_script(script: &[u8]) -> Result<Vec<ScriptOp>, String> {
                 ops.push(ScriptOp::TextBase(base));
                 rest = tail;
             }
+            opcode if extension::is_extension_opcode(opcode) => {
+                let (op, tail) = extension::parse(opcode, rest)?;
+                ops.extend(op);
+                rest = tail;
+            }
             _ => {
                 return Err(format!("unsupported opcode: 0x{opcode:02x}"));
             }
         }
     }
+    extension::check_backdrop_blur(&ops)?;
     Ok(ops)
 }
 
@@ -2223,6 +2620,63 @@ mod tests {
         assert_eq!(ops, vec![ScriptOp::DrawText("hi".to_string())]);
     }
 
+    #[test]
+    fn parse_skips_unknown_extensions() {
+        let script: [u8; 32] = [
+            0xFF, 0xFF, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x01, 0x02, 0x03, 0x00, 0xF0, 0x01,
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x0A, 0x00, 0x02,
+            b'h', b'i', 0x00, 0x00,
+        ];
+        let ops = parse_script(&script).expect("parse_script failed");
+        assert_eq!(
+            ops,
+            vec![
+                ScriptOp::TextDrawMode(renderer::TextDrawMode::Stroke),
+                ScriptOp::DrawText("hi".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_mixes_standard_ops_and_extensions() {
+        let mut script = vec![0x00, 0x40, 0x00, 0x00];
+        script.extend_from_slice(&[0x00, 0x60, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF]);
+        script.extend_from_slice(&[0xF0, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x0C]);
+        script.extend_from_slice(&[0x00, 0x01, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00]);
+        push_f32(&mut script, 100.0);
+        script.extend_from_slice(&[0xF0, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04]);
+        push_f32(&mut script, 1.5);
+        script.extend_from_slice(&[0x00, 0x0A, 0x00, 0x02, b'h', b'i', 0x00, 0x00]);
+        script.extend_from_slice(&[0x00, 0x41, 0x00, 0x00]);
+
+        let ops = parse_script(&script).expect("parse_script failed");
+        assert_eq!(
+            ops,
+            vec![
+                ScriptOp::PushState,
+                ScriptOp::FillColor(skia_safe::Color::from_argb(0xFF, 0xFF, 0x00, 0x00)),
+                ScriptOp::TextWrap(Some(renderer::TextWrap {
+                    max_width: 100.0,
+                    mode: renderer::TextWrapMode::Word,
+                    overflow: renderer::TextOverflow::Ellipsis,
+                    max_lines: 2,
+                })),
+                ScriptOp::LetterSpacing(1.5),
+                ScriptOp::DrawText("hi".to_string()),
+                ScriptOp::PopState,
+            ]
+        );
+
+        // Invalid payloads of known extensions fail the whole script.
+        let mut invalid = vec![0x00, 0x40, 0x00, 0x00];
+        invalid.extend_from_slice(&[0xF0, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02]);
+        invalid.extend_from_slice(&[0x00, 0x03, 0x00, 0x00]);
+        assert_eq!(
+            parse_script(&invalid).unwrap_err(),
+            "unsupported text_draw_mode value"
+        );
+    }
+
     #[test]
     fn parse_finished_marker() {
         let script: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
@@ -2441,6 +2895,117 @@ mod tests {
         );
     }
 
+    #[test]
+    fn set_script_indexes_draw_script_dependencies() {
+        let mut state = Scene::default();
+        set_script(
+            &mut state,
+            ROOT_ID.to_string(),
+            vec![
+                ScriptOp::DrawScript("child".to_string()),
+                ScriptOp::DrawScript("other".to_string()),
+            ],
+        );
+        set_script(
+            &mut state,
+            "child".to_string(),
+            vec![ScriptOp::DrawScript("leaf".to_string())],
+        );
+
+        assert_eq!(
+            state.dependents["leaf"],
+            HashSet::from(["child".to_string()])
+        );
+        assert_eq!(
+            state.ancestors("leaf"),
+            HashSet::from(["child".to_string(), ROOT_ID.to_string()])
+        );
+
+        let root_revision = state.state.tree_revision(ROOT_ID);
+        set_script(&mut state, "leaf".to_string(), Vec::new());
+        assert!(state.state.tree_revision(ROOT_ID) > root_revision);
+
+        set_script(&mut state, "child".to_string(), Vec::new());
+        assert!(!state.dependents.contains_key("leaf"));
+        assert!(!state.dependencies.contains_key("child"));
+
+        state.remove_script("child");
+        assert_eq!(
+            state.dependents["child"],
+            HashSet::from([ROOT_ID.to_string()])
+        );
+    }
+
+    #[test]
+    fn set_script_tracks_media_drawn_by_each_script() {
+        let mut state = Scene::default();
+        set_script(
+            &mut state,
+            "a".to_string(),
+            vec![
+                ScriptOp::FillImage("photo".to_string()),
+                ScriptOp::FillImage("photo".to_string()),
+                ScriptOp::FillStream("camera".to_string()),
+            ],
+        );
+        set_script(
+            &mut state,
+            "b".to_string(),
+            vec![ScriptOp::StrokeImage("photo".to_string())],
+        );
+        assert_eq!(state.media_refs().images["photo"], 2);
+        assert_eq!(state.media_refs().streams["camera"], 1);
+
+        set_script(&mut state, "a".to_string(), Vec::new());
+        assert_eq!(state.media_refs().images["photo"], 1);
+        assert!(state.media_refs().streams.is_empty());
+
+        state.remove_script("b");
+        assert!(state.media_refs().is_empty());
+    }
+
+    #[test]
+    fn image_changes_invalidate_only_the_scripts_drawing_them() {
+        let mut state = Scene::default();
+        set_script(
+            &mut state,
+            ROOT_ID.to_string(),
+            vec![
+                ScriptOp::DrawScript("photo".to_string()),
+                ScriptOp::DrawScript("label".to_string()),
+            ],
+        );
+        set_script(
+            &mut state,
+            "photo".to_string(),
+            vec![ScriptOp::FillImage("logo".to_string())],
+        );
+        set_script(
+            &mut state,
+            "label".to_string(),
+            vec![ScriptOp::DrawText("hi".to_string())],
+        );
+
+        let revisions = |state: &Scene| {
+            [ROOT_ID, "photo", "label"].map(|id| {
+                (
+                    state.state.script_revisions.get(id).copied(),
+                    state.state.tree_revision(id),
+                )
+            })
+        };
+        let [root, photo, label] = revisions(&state);
+        state.image_changed("logo");
+        let [new_root, new_photo, new_label] = revisions(&state);
+        assert_eq!(new_root.0, root.0);
+        assert!(new_root.1 > root.1);
+        assert!(new_photo.0 > photo.0 && new_photo.1 > photo.1);
+        assert_eq!(new_label, label);
+
+        state.image_changed("other");
+        assert_eq!(revisions(&state), [new_root, new_photo, new_label]);
+    }
+
     #[test]
     fn drain_input_events_returns_queued_events() {
         let stop = Arc::new(AtomicBool::new(false));
@@ -2460,12 +3025,17 @@ mod tests {
 
         let handle = DriverHandle {
             stop: StopSignal::Raster(Arc::clone(&stop)),
-            text: Arc::new(Mutex::new(String::new())),
-            render_state: Arc::new(Mutex::new(RenderState::default())),
+            scene: Scene::default(),
+            published_state: Arc::new(ArcSwap::from_pointee(RenderState::default())),
+            in_update: false,
+            pending_assets: Vec::new(),
             input_events: Arc::clone(&input_events),
             input_mask: Arc::new(AtomicU32::new(0)),
             raster_frame: None,
-            dirty: Some(Arc::new(AtomicBool::new(false))),
+            frame_signal: Some(Arc::new(FrameSignal::new().expect("eventfd"))),
+            presentation: Arc::new(PresentationNotifier::default()),
+            assets: Arc::new(AssetCache::default()),
+            measure_engine: Arc::default(),
             running: Arc::new(AtomicBool::new(false)),
             cursor_state: None,
             thread: Some(thread),
@@ -2481,6 +3051,60 @@ mod tests {
         assert!(matches!(drained[2], InputEvent::ViewportReshape { .. }));
     }
 
+    #[test]
+    fn updates_are_published_on_commit() {
+        let published_state = Arc::new(ArcSwap::from_pointee(RenderState::default()));
+        let frame_signal = Arc::new(FrameSignal::new().expect("eventfd"));
+        let handle = DriverHandle {
+            stop: StopSignal::Raster(Arc::new(AtomicBool::new(false))),
+            scene: Scene::default(),
+            published_state: Arc::clone(&published_state),
+            in_update: false,
+            pending_assets: Vec::new(),
+            input_events: Arc::new(Mutex::new(InputQueue::new())),
+            input_mask: Arc::new(AtomicU32::new(0)),
+            raster_frame: None,
+            frame_signal: Some(Arc::clone(&frame_signal)),
+            presentation: Arc::new(PresentationNotifier::default()),
+            assets: Arc::new(AssetCache::default()),
+            measure_engine: Arc::default(),
+            running: Arc::new(AtomicBool::new(false)),
+            cursor_state: None,
+            thread: None,
+        };
+        let renderer = RendererResource {
+            handle: Mutex::new(handle),
+        };
+
+        begin_update_inner(&renderer).expect("begin_update failed");
+        update_scene(&renderer, |state| {
+            set_script(
+                state,
+                ROOT_ID.to_string(),
+                vec![ScriptOp::DrawScript("child".to_string())],
+            );
+            Ok(())
+        })
+        .expect("set root failed");
+        update_scene(&renderer, |state| {
+            set_script(state, "child".to_string(), Vec::new());
+            Ok(())
+        })
+        .expect("set child failed");
+        assert!(published_state.load().scripts.is_empty());
+        assert!(!frame_signal.is_pending());
+
+        assert_eq!(
+            begin_update_inner(&renderer),
+            Err("update already in progress".to_string())
+        );
+
+        commit_update_inner(&renderer).expect("commit_update failed");
+        assert_eq!(published_state.load().scripts.len(), 2);
+        assert!(frame_signal.take());
+        begin_update_inner(&renderer).expect("begin_update after commit failed");
+    }
+
     #[test]
     fn parse_draw_rrect() {
         let script: [u8; 16] = [
@@ -0,0 +1,62 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rustler::{Encoder, LocalPid, OwnedEnv};
+
+rustler::atoms! {
+    frame_presented
+}
+
+/// Counts frames as they reach the screen and reports them to a registered process.
+#[derive(Default)]
+pub struct PresentationNotifier {
+    target: Mutex<Option<LocalPid>>,
+    frame_number: AtomicU64,
+}
+
+impl PresentationNotifier {
+    pub fn set_target(&self, target: Option<LocalPid>) {
+        if let Ok(mut current) = self.target.lock() {
+            *current = target;
+        }
+    }
+
+    /// Records a presented frame and sends `{:frame_presented, frame_number, monotonic_ns}`
+    /// to the target, if any. Must be called from a render thread, not from a NIF.
+    pub fn frame_presented(&self) -> u64 {
+        let frame_number = self.frame_number.fetch_add(1, Ordering::Relaxed) + 1;
+        let target = self.target.lock().ok().and_then(|target| *target);
+        if let Some(pid) = target {
+            let timestamp = monotonic_ns();
+            let mut env = OwnedEnv::new();
+            let _ = env.send_and_clear(&pid, |env| {
+                (frame_presented(), frame_number, timestamp).encode(env)
+            });
+        }
+        frame_number
+    }
+}
+
+/// Nanoseconds on the OS monotonic clock (`CLOCK_MONOTONIC`).
+pub fn monotonic_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    (ts.tv_sec as u64) * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_numbers_increase_without_target() {
+        let notifier = PresentationNotifier::default();
+        assert_eq!(notifier.frame_presented(), 1);
+        assert_eq!(notifier.frame_presented(), 2);
+    }
+}
@@ -2,13 +2,16 @@ use std::sync::{
     Arc, Mutex,
     atomic::{AtomicBool, AtomicU32, Ordering},
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use skia_safe::{AlphaType, ColorType, ImageInfo, image::CachingHint, surfaces};
 
 use crate::{
     RasterFrame,
-    renderer::{RenderState, Renderer},
+    assets::AssetCache,
+    frame_signal::FrameSignal,
+    presentation::PresentationNotifier,
+    renderer::{Renderer, SharedRenderState},
 };
 
 fn store_frame(
@@ -53,13 +56,17 @@ fn store_frame(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     stop: Arc<AtomicBool>,
-    dirty: Arc<AtomicBool>,
-    render_state: Arc<Mutex<RenderState>>,
+    frame_signal: Arc<FrameSignal>,
+    render_state: SharedRenderState,
     frame_slot: Arc<Mutex<Option<RasterFrame>>>,
     input_mask: Arc<AtomicU32>,
     requested_size: Option<(u32, u32)>,
+    min_frame_interval: Option<Duration>,
+    presentation: Arc<PresentationNotifier>,
+    assets: Arc<AssetCache>,
 ) {
     let _input_mask = input_mask;
     let (width, height) = requested_size.unwrap_or((800, 600));
@@ -73,26 +80,33 @@ pub fn run(
         None,
     );
 
+    let props = assets.font_rendering().surface_props();
     let surface =
-        surfaces::raster(&image_info, None, None).expect("Failed to create raster surface");
+        surfaces::raster(&image_info, None, Some(&props)).expect("Failed to create raster surface");
 
-    let mut renderer = Renderer::from_surface(surface, None);
-    if let Ok(state) = render_state.lock() {
-        renderer.redraw(&state);
-    }
+    let mut renderer = Renderer::from_surface(surface, None, assets);
+    renderer.redraw(&render_state.load(), 1);
 
     store_frame(&mut renderer, &frame_slot, (width, height));
+    presentation.frame_presented();
+    let mut next_frame_at = min_frame_interval.map(|interval| Instant::now() + interval);
 
     loop {
         if stop.load(Ordering::Relaxed) {
             break;
         }
-        if dirty.swap(false, Ordering::Relaxed) {
-            if let Ok(state) = render_state.lock() {
-                renderer.redraw(&state);
-            }
+        frame_signal.wait_until(None);
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        if let Some(deadline) = next_frame_at {
+            std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
+        if frame_signal.take() {
+            renderer.redraw(&render_state.load(), 1);
             store_frame(&mut renderer, &frame_slot, (width, height));
+            presentation.frame_presented();
+            next_frame_at = min_frame_interval.map(|interval| Instant::now() + interval);
         }
-        std::thread::sleep(Duration::from_millis(100));
     }
 }
@@ -1,19 +1,36 @@
-use std::collections::HashMap;
-use std::sync::{Mutex, OnceLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
 
 use skia_safe::{
-    AlphaType, ClipOp, Color, ColorType, Data, FilterMode, Font, FontMgr, FontStyle, Image,
+    AlphaType, BlendMode, ClipOp, Color, ColorType, Data, FilterMode, IRect, Image, ImageFilter,
     ImageInfo, Matrix, MipmapMode, Paint, PaintCap, PaintJoin, PaintStyle, PathBuilder,
-    PathDirection, Point, RRect, Rect, SamplingOptions, Shader, Surface, TileMode, Typeface,
-    Vector,
-    canvas::SrcRectConstraint,
+    PathDirection, Picture, PictureRecorder, Point, RRect, Rect, SamplingOptions, Shader, Surface,
+    SurfaceProps, TileMode, Vector,
+    canvas::{SaveLayerRec, SrcRectConstraint},
     gpu::{self, SurfaceOrigin, backend_render_targets, gl::FramebufferInfo},
+    image_filters::{self, CropRect},
     images,
 };
 
+use crate::assets::{AssetCache, MediaRefs};
+use crate::damage::{DamageTracker, ScriptBounds};
+use crate::text::TextEngine;
+
+/// Pictures kept per script, one per inherited style it was drawn with, so a script
+/// drawn in a few styles (e.g. a button label in its normal and pressed colors) keeps
+/// all of them.
+const PICTURES_PER_SCRIPT: usize = 4;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum ScriptOp {
     PushState,
+    PushLayer(Layer),
+    BlendMode(BlendMode),
+    DropShadow(Option<DropShadow>),
+    Blur(Option<Blur>),
+    BackdropBlur(Option<Blur>),
     PopState,
     PopPushState,
     Translate(f32, f32),
@@ -62,6 +79,8 @@ pub enum ScriptOp {
         start_color: Color,
         end_color: Color,
     },
+    FillGradient(Gradient),
+    StrokeGradient(Gradient),
     FillImage(String),
     FillStream(String),
     StrokeImage(String),
@@ -228,6 +247,10 @@ pub enum ScriptOp {
     FontSize(f32),
     TextAlign(TextAlign),
     TextBase(TextBase),
+    TextDrawMode(TextDrawMode),
+    TextDecoration(TextDecoration),
+    LetterSpacing(f32),
+    TextWrap(Option<TextWrap>),
     DrawScript(String),
 }
 
@@ -244,33 +267,208 @@ pub struct SpriteCommand {
     pub alpha: f32,
 }
 
+/// Scene snapshot rendered by a backend thread.
+///
+/// Scripts and revisions live in persistent maps, so publishing a snapshot after an edit
+/// is O(1) and only the entries the edit touched stop being shared with the previous one.
 #[derive(Clone, Debug)]
 pub struct RenderState {
     pub clear_color: Color,
-    pub scripts: HashMap<String, Vec<ScriptOp>>,
+    pub scripts: im::HashMap<String, Arc<Vec<ScriptOp>>>,
+    /// Revision of each script, bumped whenever the script is replaced.
+    pub script_revisions: im::HashMap<String, u64>,
+    /// Bumped for a script and all of its ancestors whenever anything it draws changes.
+    pub tree_revisions: im::HashMap<String, u64>,
+    pub revision: u64,
     pub root_id: Option<String>,
 }
 
-static IMAGE_CACHE: OnceLock<Mutex<HashMap<String, Image>>> = OnceLock::new();
-static STREAM_CACHE: OnceLock<Mutex<HashMap<String, Image>>> = OnceLock::new();
-static FONT_CACHE: OnceLock<Mutex<HashMap<String, Typeface>>> = OnceLock::new();
+/// Latest published scene, read by the render thread without blocking NIFs.
+pub type SharedRenderState = Arc<ArcSwap<RenderState>>;
 
 impl Default for RenderState {
     fn default() -> Self {
         Self {
             clear_color: Color::WHITE,
-            scripts: HashMap::new(),
+            scripts: im::HashMap::default(),
+            script_revisions: im::HashMap::default(),
+            tree_revisions: im::HashMap::default(),
+            revision: 0,
             root_id: None,
         }
     }
 }
 
+impl RenderState {
+    fn script_revision(&self, id: &str) -> u64 {
+        self.script_revisions.get(id).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn tree_revision(&self, id: &str) -> u64 {
+        self.tree_revisions.get(id).copied().unwrap_or(0)
+    }
+}
+
+/// Scene edited by NIFs: the `RenderState` published after each update, plus the
+/// `DrawScript` dependency index, which only the NIF side reads.
+#[derive(Default)]
+pub struct Scene {
+    pub state: RenderState,
+    /// Script ids each script draws through `DrawScript`.
+    pub dependencies: HashMap<String, HashSet<String>>,
+    /// Reverse of `dependencies`: script ids that draw each script. Entries remain for
+    /// ids that are referenced but not (or no longer) submitted.
+    pub dependents: HashMap<String, HashSet<String>>,
+    /// Images and streams each script draws, found when the script is submitted.
+    script_media: HashMap<String, MediaRefs>,
+    /// Sum of `script_media`.
+    media: MediaRefs,
+}
+
+impl Scene {
+    pub fn insert_script(&mut self, id: String, ops: Vec<ScriptOp>) {
+        let dependencies: HashSet<String> = ops
+            .iter()
+            .filter_map(|op| match op {
+                ScriptOp::DrawScript(child) => Some(child.clone()),
+                _ => None,
+            })
+            .collect();
+        self.unlink_dependencies(&id);
+        for child in &dependencies {
+            self.dependents
+                .entry(child.clone())
+                .or_default()
+                .insert(id.clone());
+        }
+        if !dependencies.is_empty() {
+            self.dependencies.insert(id.clone(), dependencies);
+        }
+        self.unlink_media(&id);
+        let media = script_media_refs(&ops);
+        if !media.is_empty() {
+            self.media.add(&media);
+            self.script_media.insert(id.clone(), media);
+        }
+
+        let state = &mut self.state;
+        state.revision += 1;
+        state.script_revisions.insert(id.clone(), state.revision);
+        state.scripts.insert(id.clone(), Arc::new(ops));
+        self.invalidate_tree(&id);
+    }
+
+    pub fn remove_script(&mut self, id: &str) {
+        self.unlink_dependencies(id);
+        self.unlink_media(id);
+        self.state.script_revisions.remove(id);
+        self.state.scripts.remove(id);
+        self.invalidate_tree(id);
+        self.state.tree_revisions.remove(id);
+    }
+
+    pub fn clear_scripts(&mut self) {
+        let state = &mut self.state;
+        state.scripts = im::HashMap::default();
+        state.script_revisions = im::HashMap::default();
+        state.tree_revisions = im::HashMap::default();
+        state.root_id = None;
+        self.dependencies.clear();
+        self.dependents.clear();
+        self.script_media.clear();
+        self.media = MediaRefs::default();
+    }
+
+    /// Images and streams drawn by any submitted script.
+    pub fn media_refs(&self) -> &MediaRefs {
+        &self.media
+    }
+
+    /// Marks every script that draws the static image `id` as changed, so their pictures
+    /// are re-recorded and the area they cover is redrawn.
+    pub fn image_changed(&mut self, id: &str) {
+        let scripts: Vec<String> = self
+            .script_media
+            .iter()
+            .filter(|(_, media)| media.images.contains_key(id))
+            .map(|(script, _)| script.clone())
+            .collect();
+        for script in scripts {
+            self.state.revision += 1;
+            let revision = self.state.revision;
+            self.state.script_revisions.insert(script.clone(), revision);
+            self.invalidate_tree(&script);
+        }
+    }
+
+    /// Every script that draws `id`, directly or through other scripts.
+    pub fn ancestors(&self, id: &str) -> HashSet<String> {
+        let mut ancestors = HashSet::new();
+        let mut pending = vec![id];
+        while let Some(current) = pending.pop() {
+            if let Some(parents) = self.dependents.get(current) {
+                for parent in parents {
+                    if ancestors.insert(parent.clone()) {
+                        pending.push(parent.as_str());
+                    }
+                }
+            }
+        }
+        ancestors
+    }
+
+    fn invalidate_tree(&mut self, id: &str) {
+        self.state.revision += 1;
+        let revision = self.state.revision;
+        self.state.tree_revisions.insert(id.to_string(), revision);
+        for ancestor in self.ancestors(id) {
+            self.state.tree_revisions.insert(ancestor, revision);
+        }
+    }
+
+    fn unlink_media(&mut self, id: &str) {
+        if let Some(previous) = self.script_media.remove(id) {
+            self.media.remove(&previous);
+        }
+    }
+
+    fn unlink_dependencies(&mut self, id: &str) {
+        let Some(previous) = self.dependencies.remove(id) else {
+            return;
+        };
+        for child in previous {
+            if let Some(parents) = self.dependents.get_mut(&child) {
+                parents.remove(id);
+                if parents.is_empty() {
+                    self.dependents.remove(&child);
+                }
+            }
+        }
+    }
+}
+
+/// Images and streams drawn by `ops`, each counted once.
+fn script_media_refs(ops: &[ScriptOp]) -> MediaRefs {
+    let mut refs = MediaRefs::default();
+    for op in ops {
+        let (ids, id) = match op {
+            ScriptOp::FillImage(id) | ScriptOp::StrokeImage(id) => (&mut refs.images, id),
+            ScriptOp::DrawSprites { image_id, .. } => (&mut refs.images, image_id),
+            ScriptOp::FillStream(id) | ScriptOp::StrokeStream(id) => (&mut refs.streams, id),
+            _ => continue,
+        };
+        ids.insert(id.clone(), 1);
+    }
+    refs
+}
+
 fn create_skia_surface(
     dimensions: (i32, i32),
     fb_info: FramebufferInfo,
     gr_context: &mut skia_safe::gpu::DirectContext,
     num_samples: usize,
     stencil_size: usize,
+    props: &SurfaceProps,
 ) -> Surface {
     let backend_render_target =
         backend_render_targets::make_gl(dimensions, num_samples, stencil_size, fb_info);
@@ -281,7 +479,7 @@ fn create_skia_surface(
         SurfaceOrigin::BottomLeft,
         ColorType::RGBA8888,
         None,
-        None,
+        Some(props),
     )
     .expect("Could not create Skia surface")
 }
@@ -301,6 +499,9 @@ pub struct Renderer {
     gr_context: Option<skia_safe::gpu::DirectContext>,
     source: SurfaceSource,
     scale_factor: f32,
+    assets: Arc<AssetCache>,
+    text: TextEngine,
+    picture_cache: PictureCache,
 }
 
 impl Renderer {
@@ -310,6 +511,7 @@ impl Renderer {
         gr_context: skia_safe::gpu::DirectContext,
         num_samples: usize,
         stencil_size: usize,
+        assets: Arc<AssetCache>,
     ) -> Self {
         let mut gr_context = gr_context;
         let surface = create_skia_surface(
@@ -318,6 +520,7 @@ impl Renderer {
             &mut gr_context,
             num_samples,
             stencil_size,
+            &assets.font_rendering().surface_props(),
         );
 
         Self {
@@ -329,34 +532,56 @@ impl Renderer {
                 stencil_size,
             },
             scale_factor: 1.0,
+            assets,
+            text: TextEngine::default(),
+            picture_cache: PictureCache::default(),
         }
     }
 
     pub fn from_surface(
         surface: Surface,
         gr_context: Option<skia_safe::gpu::DirectContext>,
+        assets: Arc<AssetCache>,
     ) -> Self {
         Self {
             surface,
             gr_context,
             source: SurfaceSource::Raster,
             scale_factor: 1.0,
+            assets,
+            text: TextEngine::default(),
+            picture_cache: PictureCache::default(),
         }
     }
 
     pub fn set_scale_factor(&mut self, scale_factor: f32) {
-        self.scale_factor = scale_factor.max(0.1);
+        let scale_factor = scale_factor.max(0.1);
+        if scale_factor != self.scale_factor {
+            self.picture_cache.damage.invalidate();
+        }
+        self.scale_factor = scale_factor;
     }
 
     pub fn surface_mut(&mut self) -> &mut Surface {
         &mut self.surface
     }
 
-    pub fn redraw(&mut self, render_state: &RenderState) {
-        let canvas = self.surface.canvas();
-        canvas.clear(render_state.clear_color);
-
-        canvas.save();
+    /// Redraws the parts of the surface that changed and returns the redrawn region.
+    ///
+    /// `buffer_age` is the age of the target buffer as reported by `EGL_EXT_buffer_age`:
+    /// 1 when it holds the previous frame, 0 when its contents are undefined.
+    pub fn redraw(&mut self, render_state: &RenderState, buffer_age: u32) -> IRect {
+        let font_generation = self.assets.font_generation();
+        self.picture_cache.retain_scripts(render_state);
+        self.picture_cache.font_generation = font_generation;
+        self.text.begin_frame(font_generation);
+        self.picture_cache
+            .damage
+            .begin_frame(render_state.clear_color, font_generation);
+
+        let bounds = IRect::from_wh(self.surface.width(), self.surface.height());
+        let mut recorder = PictureRecorder::new();
+        let canvas = recorder.begin_recording(Rect::from(bounds), true);
         if (self.scale_factor - 1.0).abs() > f32::EPSILON {
             canvas.scale((self.scale_factor, self.scale_factor));
         }
@@ -364,20 +589,44 @@ impl Renderer {
         if let Some(root_id) = render_state.root_id.clone() {
             let mut draw_state = DrawState::default();
             let mut stack_ids = Vec::new();
+            let mut resources = Resources {
+                assets: &self.assets,
+                text: &mut self.text,
+            };
             draw_script(
                 render_state,
+                &mut resources,
                 &root_id,
                 canvas,
                 &mut draw_state,
                 &mut stack_ids,
+                &mut self.picture_cache,
             );
         }
 
+        let frame = recorder.finish_recording_as_picture(None);
+        let damage = self.picture_cache.damage.finish_frame(bounds, buffer_age);
+
+        let canvas = self.surface.canvas();
+        canvas.save();
+        canvas.clip_irect(damage, None);
+        canvas.clear(render_state.clear_color);
+        if let Some(frame) = frame {
+            canvas.draw_picture(&frame, None, None);
+        }
         canvas.restore();
 
         if let Some(gr) = self.gr_context.as_mut() {
             gr.flush_and_submit();
         }
+
+        damage
+    }
+
+    /// Adds content drawn on top of the scripts, such as a software cursor, to the next
+    /// frame's damage.
+    pub fn add_damage(&mut self, rect: Rect) {
+        self.picture_cache.damage.add_damage(rect);
     }
 
     pub fn resize(&mut self, dimensions: (u32, u32)) {
@@ -394,17 +643,27 @@ impl Renderer {
                 context,
                 num_samples,
                 stencil_size,
+                &self.assets.font_rendering().surface_props(),
             );
         }
+        self.picture_cache.damage.invalidate();
     }
 }
 
+/// Assets and the text engine the replay draws with.
+struct Resources<'a> {
+    assets: &'a AssetCache,
+    text: &'a mut TextEngine,
+}
+
 fn draw_script(
     render_state: &RenderState,
+    resources: &mut Resources,
     script_id: &str,
     canvas: &skia_safe::Canvas,
     draw_state: &mut DrawState,
     stack_ids: &mut Vec<String>,
+    cache: &mut PictureCache,
 ) {
     if stack_ids.iter().any(|id| id == script_id) {
         return;
@@ -414,14 +673,104 @@ fn draw_script(
         Some(ops) => ops,
         None => return,
     };
+    let revision = render_state.script_revision(script_id);
+    let info = cache.script_info(script_id, revision, ops);
+
+    if let Some(key) = draw_state.cache_key()
+        && info.self_contained
+    {
+        if let Some(entry) = cache.lookup(render_state, script_id, &key) {
+            canvas.draw_picture(&entry.picture, None, None);
+            cache.note_bounds(canvas, &entry.bounds);
+            draw_state.apply_snapshot(entry.exit_state);
+            return;
+        }
+
+        cache.begin_recording();
+        let mut recorder = PictureRecorder::new();
+        let recording_canvas = recorder.begin_recording(PictureCache::recording_bounds(), true);
+        stack_ids.push(script_id.to_string());
+        replay_ops(
+            render_state,
+            resources,
+            ops,
+            recording_canvas,
+            draw_state,
+            stack_ids,
+            cache,
+        );
+        stack_ids.pop();
+        let recording = cache.end_recording();
+        if let Some(picture) = recorder.finish_recording_as_picture(None) {
+            let mut bounds = vec![ScriptBounds {
+                id: script_id.to_string(),
+                revision,
+                rect: Some(picture.cull_rect()),
+                volatile: info.uses_streams,
+                backdrop: info.uses_backdrop,
+            }];
+            bounds.extend(recording.bounds);
+
+            canvas.draw_picture(&picture, None, None);
+            cache.note_bounds(canvas, &bounds);
+            cache.store(
+                script_id,
+                CachedPicture {
+                    picture,
+                    entry_key: key,
+                    exit_state: draw_state.snapshot(),
+                    bounds,
+                    tree_revision: render_state.tree_revision(script_id),
+                    font_generation: cache.font_generation,
+                },
+                recording.volatile,
+            );
+        }
+        return;
+    }
 
+    cache.note_bounds(
+        canvas,
+        &[ScriptBounds {
+            id: script_id.to_string(),
+            revision,
+            rect: None,
+            volatile: info.uses_streams,
+            backdrop: info.uses_backdrop,
+        }],
+    );
     stack_ids.push(script_id.to_string());
+    replay_ops(
+        render_state,
+        resources,
+        ops,
+        canvas,
+        draw_state,
+        stack_ids,
+        cache,
+    );
+    stack_ids.pop();
+}
 
+fn replay_ops(
+    render_state: &RenderState,
+    resources: &mut Resources,
+    ops: &[ScriptOp],
+    canvas: &skia_safe::Canvas,
+    draw_state: &mut DrawState,
+    stack_ids: &mut Vec<String>,
+    cache: &mut PictureCache,
+) {
     for op in ops {
         match op {
             ScriptOp::PushState => {
                 canvas.save();
-                draw_state.push();
+                draw_state.push(None);
+            }
+            ScriptOp::PushLayer(layer) => {
+                layer.save(canvas, draw_state);
+                draw_state.push(Some(*layer));
+                draw_state.enter_layer();
             }
             ScriptOp::PopState => {
                 if draw_state.can_pop() {
@@ -432,8 +781,15 @@ fn draw_script(
             ScriptOp::PopPushState => {
                 if draw_state.can_pop() {
                     canvas.restore();
-                    canvas.save();
-                    draw_state.pop_push();
+                    match draw_state.pop_push() {
+                        Some(layer) => {
+                            layer.save(canvas, draw_state);
+                            draw_state.enter_layer();
+                        }
+                        None => {
+                            canvas.save();
+                        }
+                    }
                 }
             }
             ScriptOp::Translate(x, y) => {
@@ -488,11 +844,13 @@ fn draw_script(
                 draw_state.fill_color = *start_color;
                 let colors = [*start_color, *end_color];
                 draw_state.fill_shader = radial_shader(
-                    *center_x,
-                    *center_y,
+                    Point::new(*center_x, *center_y),
                     *inner_radius,
                     *outer_radius,
                     colors.as_slice(),
+                    None,
+                    TileMode::Clamp,
+                    None,
                 );
             }
             ScriptOp::StrokeLinear {
@@ -525,24 +883,48 @@ fn draw_script(
                 draw_state.stroke_color = *start_color;
                 let colors = [*start_color, *end_color];
                 draw_state.stroke_shader = radial_shader(
-                    *center_x,
-                    *center_y,
+                    Point::new(*center_x, *center_y),
                     *inner_radius,
                     *outer_radius,
                     colors.as_slice(),
+                    None,
+                    TileMode::Clamp,
+                    None,
                 );
             }
+            ScriptOp::FillGradient(gradient) => {
+                draw_state.fill_color = gradient.stops[0].1;
+                draw_state.fill_shader = gradient.shader();
+            }
+            ScriptOp::StrokeGradient(gradient) => {
+                draw_state.stroke_color = gradient.stops[0].1;
+                draw_state.stroke_shader = gradient.shader();
+            }
             ScriptOp::FillImage(id) => {
-                set_fill_image_shader(draw_state, load_static_shader(id.as_str()));
+                set_fill_image_shader(
+                    draw_state,
+                    load_static_shader(resources.assets, id.as_str()),
+                );
             }
             ScriptOp::FillStream(id) => {
-                set_fill_image_shader(draw_state, load_stream_shader(id.as_str()));
+                cache.mark_volatile();
+                set_fill_image_shader(
+                    draw_state,
+                    load_stream_shader(resources.assets, id.as_str()),
+                );
             }
             ScriptOp::StrokeImage(id) => {
-                set_stroke_image_shader(draw_state, load_static_shader(id.as_str()));
+                set_stroke_image_shader(
+                    draw_state,
+                    load_static_shader(resources.assets, id.as_str()),
+                );
             }
             ScriptOp::StrokeStream(id) => {
-                set_stroke_image_shader(draw_state, load_stream_shader(id.as_str()));
+                cache.mark_volatile();
+                set_stroke_image_shader(
+                    draw_state,
+                    load_stream_shader(resources.assets, id.as_str()),
+                );
             }
             ScriptOp::StrokeCap(cap) => draw_state.stroke_cap = *cap,
             ScriptOp::StrokeJoin(join) => draw_state.stroke_join = *join,
@@ -914,7 +1296,7 @@ fn draw_script(
                 }
             }
             ScriptOp::DrawSprites { image_id, cmds } => {
-                let Some(image) = cached_static_image(image_id.as_str()) else {
+                let Some(image) = resources.assets.static_image(image_id.as_str()) else {
                     continue;
                 };
                 for cmd in cmds {
@@ -922,6 +1304,8 @@ fn draw_script(
                     let dst = Rect::from_xywh(cmd.dx, cmd.dy, cmd.dw, cmd.dh);
                     let mut paint = Paint::default();
                     paint.set_alpha_f(cmd.alpha);
+                    paint.set_blend_mode(draw_state.blend_mode);
+                    paint.set_image_filter(draw_state.image_filter());
                     canvas.draw_image_rect_with_sampling_options(
                         &image,
                         Some((&src, SrcRectConstraint::Fast)),
@@ -932,35 +1316,262 @@ fn draw_script(
                 }
             }
             ScriptOp::DrawText(text) => {
-                let font = match draw_state.font_id.as_deref() {
-                    Some(font_id) => font_from_asset(font_id, draw_state.font_size),
-                    None => default_font(draw_state.font_size),
-                };
-                if let Some(font) = font.as_ref()
-                    && !text.is_empty()
-                {
-                    let mut paint = Paint::default();
-                    apply_fill_paint(&mut paint, draw_state);
-                    let (dx, dy) = draw_state.text_offsets(text, font, &paint);
-                    canvas.draw_str(text, (dx, dy), font, &paint);
+                if text.is_empty() {
+                    continue;
+                }
+                let prepared = resources.text.prepare(
+                    resources.assets,
+                    draw_state.font_id.as_deref(),
+                    draw_state.font_size,
+                    text,
+                    draw_state.text_align,
+                    draw_state.text_base,
+                    draw_state.letter_spacing,
+                    draw_state.text_wrap,
+                );
+                if let Some(prepared) = prepared {
+                    let clip = draw_state
+                        .text_wrap
+                        .filter(|wrap| wrap.overflow == TextOverflow::Clip)
+                        .map(|wrap| wrap.clip_rect(draw_state.text_align));
+                    if let Some(clip) = clip {
+                        canvas.save();
+                        canvas.clip_rect(clip, ClipOp::Intersect, true);
+                    }
+                    let layout = &prepared.layout;
+                    let decorations = layout.decoration_rects(draw_state.text_decoration);
+                    let mode = draw_state.text_draw_mode;
+                    if mode.fills() {
+                        let mut paint = Paint::default();
+                        apply_fill_paint(&mut paint, draw_state);
+                        if let Some(blob) = layout.blob.as_ref() {
+                            canvas.draw_text_blob(blob, prepared.offset, &paint);
+                        }
+                        for rect in &decorations {
+                            canvas.draw_rect(rect.with_offset(prepared.offset), &paint);
+                        }
+                        if let Some(blob) = layout.color_blob.as_ref() {
+                            let paint = color_glyph_paint(draw_state);
+                            canvas.draw_text_blob(blob, prepared.offset, &paint);
+                        }
+                    }
+                    if mode.strokes() {
+                        let mut paint = Paint::default();
+                        apply_stroke_paint(&mut paint, draw_state);
+                        if let Some(blob) = layout.blob.as_ref() {
+                            canvas.draw_text_blob(blob, prepared.offset, &paint);
+                        }
+                        for rect in &decorations {
+                            canvas.draw_rect(rect.with_offset(prepared.offset), &paint);
+                        }
+                    }
+                    if clip.is_some() {
+                        canvas.restore();
+                    }
                 }
             }
             ScriptOp::Font(font_id) => draw_state.font_id = Some(font_id.clone()),
             ScriptOp::FontSize(size) => draw_state.font_size = *size,
             ScriptOp::TextAlign(align) => draw_state.text_align = *align,
             ScriptOp::TextBase(base) => draw_state.text_base = *base,
+            ScriptOp::TextDrawMode(mode) => draw_state.text_draw_mode = *mode,
+            ScriptOp::TextDecoration(decoration) => draw_state.text_decoration = *decoration,
+            ScriptOp::LetterSpacing(spacing) => draw_state.letter_spacing = *spacing,
+            ScriptOp::BlendMode(mode) => draw_state.blend_mode = *mode,
+            ScriptOp::DropShadow(shadow) => draw_state.drop_shadow = *shadow,
+            ScriptOp::Blur(blur) => draw_state.blur = *blur,
+            ScriptOp::BackdropBlur(blur) => draw_state.backdrop_blur = *blur,
+            ScriptOp::TextWrap(wrap) => draw_state.text_wrap = *wrap,
             ScriptOp::DrawScript(id) => {
-                draw_script(render_state, id, canvas, draw_state, stack_ids);
+                draw_script(
+                    render_state,
+                    resources,
+                    id,
+                    canvas,
+                    draw_state,
+                    stack_ids,
+                    cache,
+                );
             }
         }
     }
+}
 
-    stack_ids.pop();
+/// Returns true when replaying `ops` leaves the canvas and draw-state stack exactly as it
+/// found them, so the script can be recorded once and replayed as a picture.
+fn script_is_self_contained(ops: &[ScriptOp]) -> bool {
+    let mut depth = 0usize;
+    for op in ops {
+        match op {
+            ScriptOp::PushState | ScriptOp::PushLayer(_) => depth += 1,
+            ScriptOp::PopState => {
+                if depth == 0 {
+                    return false;
+                }
+                depth -= 1;
+            }
+            ScriptOp::PopPushState
+            | ScriptOp::Translate(..)
+            | ScriptOp::Rotate(_)
+            | ScriptOp::Scale(..)
+            | ScriptOp::Transform { .. }
+            | ScriptOp::ClipPath(_)
+            | ScriptOp::Scissor { .. }
+                if depth == 0 =>
+            {
+                return false;
+            }
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+#[derive(Clone)]
+struct CachedPicture {
+    picture: Picture,
+    entry_key: StyleKey,
+    exit_state: DrawStateSnapshot,
+    /// Bounds of the script and every script recorded into it, in picture space.
+    bounds: Vec<ScriptBounds>,
+    tree_revision: u64,
+    font_generation: u64,
+}
+
+#[derive(Default)]
+struct Recording {
+    bounds: Vec<ScriptBounds>,
+    volatile: bool,
+}
+
+#[derive(Clone, Copy)]
+struct ScriptInfo {
+    revision: u64,
+    self_contained: bool,
+    uses_streams: bool,
+    uses_backdrop: bool,
+}
+
+/// Per-renderer cache of recorded script pictures.
+///
+/// A script is recorded the first time it is drawn with a given inherited style and
+/// replayed as a picture until its tree revision changes, i.e. until it or any script
+/// it draws through `DrawScript` is replaced, deleted or has a static image it draws
+/// changed, or until fonts change. Up to `PICTURES_PER_SCRIPT` styles are kept per
+/// script. Scripts that paint streams are re-recorded every frame.
+#[derive(Default)]
+struct PictureCache {
+    /// Pictures of each script, most recently used first.
+    entries: HashMap<String, Vec<CachedPicture>>,
+    script_info: HashMap<String, ScriptInfo>,
+    recordings: Vec<Recording>,
+    damage: DamageTracker,
+    /// Font generation of the frame being drawn.
+    font_generation: u64,
+}
+
+impl PictureCache {
+    fn recording_bounds() -> Rect {
+        Rect::new(-1.0e6, -1.0e6, 1.0e6, 1.0e6)
+    }
+
+    fn retain_scripts(&mut self, render_state: &RenderState) {
+        self.entries
+            .retain(|id, _| render_state.scripts.contains_key(id));
+        self.script_info
+            .retain(|id, _| render_state.scripts.contains_key(id));
+    }
+
+    fn script_info(&mut self, script_id: &str, revision: u64, ops: &[ScriptOp]) -> ScriptInfo {
+        if let Some(info) = self.script_info.get(script_id)
+            && info.revision == revision
+        {
+            return *info;
+        }
+        let info = ScriptInfo {
+            revision,
+            self_contained: script_is_self_contained(ops),
+            uses_streams: ops
+                .iter()
+                .any(|op| matches!(op, ScriptOp::FillStream(_) | ScriptOp::StrokeStream(_))),
+            uses_backdrop: ops
+                .iter()
+                .any(|op| matches!(op, ScriptOp::BackdropBlur(Some(_)))),
+        };
+        self.script_info.insert(script_id.to_string(), info);
+        info
+    }
+
+    fn lookup(
+        &mut self,
+        render_state: &RenderState,
+        script_id: &str,
+        key: &StyleKey,
+    ) -> Option<CachedPicture> {
+        let tree_revision = render_state.tree_revision(script_id);
+        let font_generation = self.font_generation;
+        let pictures = self.entries.get_mut(script_id)?;
+        // Pictures of an older tree or with older fonts are stale in every style.
+        pictures.retain(|entry| {
+            entry.tree_revision == tree_revision && entry.font_generation == font_generation
+        });
+        let index = pictures.iter().position(|entry| entry.entry_key == *key)?;
+        pictures[..=index].rotate_right(1);
+        Some(pictures[0].clone())
+    }
+
+    /// Records where scripts were drawn, either into the enclosing recording or, at the
+    /// top level, into this frame's damage tracking.
+    fn note_bounds(&mut self, canvas: &skia_safe::Canvas, bounds: &[ScriptBounds]) {
+        let matrix = canvas.local_to_device_as_3x3();
+        for script_bounds in bounds {
+            let mapped = script_bounds.mapped(&matrix);
+            match self.recordings.last_mut() {
+                Some(recording) => recording.bounds.push(mapped),
+                None => self.damage.note(&mapped),
+            }
+        }
+    }
+
+    fn mark_volatile(&mut self) {
+        if let Some(recording) = self.recordings.last_mut() {
+            recording.volatile = true;
+        }
+    }
+
+    fn begin_recording(&mut self) {
+        self.recordings.push(Recording::default());
+    }
+
+    fn end_recording(&mut self) -> Recording {
+        let recording = self.recordings.pop().unwrap_or_default();
+        if let Some(parent) = self.recordings.last_mut() {
+            parent.volatile |= recording.volatile;
+        }
+        recording
+    }
+
+    fn store(&mut self, script_id: &str, entry: CachedPicture, volatile: bool) {
+        if volatile {
+            self.entries.remove(script_id);
+            return;
+        }
+        let pictures = self.entries.entry(script_id.to_string()).or_default();
+        pictures.retain(|cached| {
+            cached.entry_key != entry.entry_key
+                && cached.tree_revision == entry.tree_revision
+                && cached.font_generation == entry.font_generation
+        });
+        pictures.insert(0, entry);
+        pictures.truncate(PICTURES_PER_SCRIPT);
+    }
 }
 
 fn apply_fill_paint(paint: &mut Paint, draw_state: &DrawState) {
     paint.set_anti_alias(true);
     paint.set_style(PaintStyle::Fill);
+    paint.set_blend_mode(draw_state.blend_mode);
+    paint.set_image_filter(draw_state.image_filter());
     if let Some(shader) = &draw_state.fill_shader {
         paint.set_shader(shader.clone());
         paint.set_color(Color::WHITE);
@@ -969,9 +1580,26 @@ fn apply_fill_paint(paint: &mut Paint, draw_state: &DrawState) {
     }
 }
 
+/// Paint for color glyphs: they keep their own colors and only take the fill's alpha,
+/// or its color for COLR layers drawn in the foreground color. Shaders don't apply.
+fn color_glyph_paint(draw_state: &DrawState) -> Paint {
+    let mut paint = Paint::default();
+    paint.set_anti_alias(true);
+    paint.set_blend_mode(draw_state.blend_mode);
+    paint.set_image_filter(draw_state.image_filter());
+    if draw_state.fill_shader.is_some() {
+        paint.set_color(Color::BLACK);
+    } else {
+        paint.set_color(draw_state.fill_color);
+    }
+    paint
+}
+
 fn apply_stroke_paint(paint: &mut Paint, draw_state: &DrawState) {
     paint.set_anti_alias(true);
     paint.set_style(PaintStyle::Stroke);
+    paint.set_blend_mode(draw_state.blend_mode);
+    paint.set_image_filter(draw_state.image_filter());
     paint.set_stroke_width(draw_state.stroke_width);
     paint.set_stroke_cap(draw_state.stroke_cap);
     paint.set_stroke_join(draw_state.stroke_join);
@@ -1004,52 +1632,16 @@ fn set_stroke_image_shader(draw_state: &mut DrawState, shader: Option<Shader>) {
     }
 }
 
-fn default_font(size: f32) -> Option<Font> {
-    static DEFAULT_TYPEFACE: OnceLock<Option<Typeface>> = OnceLock::new();
-    let typeface = DEFAULT_TYPEFACE
-        .get_or_init(|| {
-            let fm = FontMgr::new();
-            fm.match_family_style("DejaVu Sans", FontStyle::normal())
-                .or_else(|| fm.match_family_style("Sans", FontStyle::normal()))
-        })
-        .clone()?;
-    Some(Font::new(typeface, size))
+fn load_static_shader(assets: &AssetCache, id: &str) -> Option<Shader> {
+    assets
+        .static_image(id)
+        .and_then(|image| image_to_shader(&image))
 }
 
-fn font_from_asset(font_id: &str, size: f32) -> Option<Font> {
-    let typeface = typeface_from_asset(font_id)?;
-    Some(Font::new(typeface, size))
-}
-
-fn typeface_from_asset(font_id: &str) -> Option<Typeface> {
-    let cache = FONT_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-    if let Ok(cache) = cache.lock()
-        && let Some(typeface) = cache.get(font_id)
-    {
-        return Some(typeface.clone());
-    }
-    None
-}
-
-pub fn insert_font(id: &str, data: &[u8]) -> Result<(), String> {
-    let fm = FontMgr::new();
-    let typeface = fm
-        .new_from_data(data, 0)
-        .ok_or_else(|| "invalid font data".to_string())?;
-    let cache = FONT_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-    let mut cache = cache
-        .lock()
-        .map_err(|_| "font cache lock poisoned".to_string())?;
-    cache.insert(id.to_string(), typeface);
-    Ok(())
-}
-
-fn load_static_shader(id: &str) -> Option<Shader> {
-    cached_static_image(id).and_then(|image| image_to_shader(&image))
-}
-
-fn load_stream_shader(id: &str) -> Option<Shader> {
-    cached_stream_image(id).and_then(|image| image_to_shader(&image))
+fn load_stream_shader(assets: &AssetCache, id: &str) -> Option<Shader> {
+    assets
+        .stream_image(id)
+        .and_then(|image| image_to_shader(&image))
 }
 
 fn image_to_shader(image: &Image) -> Option<Shader> {
@@ -1061,84 +1653,39 @@ fn image_to_shader(image: &Image) -> Option<Shader> {
 }
 
 fn radial_shader(
-    center_x: f32,
-    center_y: f32,
+    center: Point,
     inner_radius: f32,
     outer_radius: f32,
     colors: &[Color],
+    positions: Option<&[f32]>,
+    tile_mode: TileMode,
+    local_matrix: Option<&Matrix>,
 ) -> Option<Shader> {
     if inner_radius <= 0.0 {
         Shader::radial_gradient(
-            Point::new(center_x, center_y),
+            center,
             outer_radius,
             colors,
+            positions,
+            tile_mode,
             None,
-            TileMode::Clamp,
-            None,
-            None,
+            local_matrix,
         )
     } else {
         Shader::two_point_conical_gradient(
-            Point::new(center_x, center_y),
+            center,
             inner_radius,
-            Point::new(center_x, center_y),
+            center,
             outer_radius,
             colors,
+            positions,
+            tile_mode,
             None,
-            TileMode::Clamp,
-            None,
-            None,
+            local_matrix,
         )
     }
 }
 
-fn cached_static_image(id: &str) -> Option<Image> {
-    let cache = IMAGE_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-
-    if let Ok(cache) = cache.lock()
-        && let Some(image) = cache.get(id)
-    {
-        return Some(image.clone());
-    }
-    None
-}
-
-fn cached_stream_image(id: &str) -> Option<Image> {
-    let cache = STREAM_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-
-    if let Ok(cache) = cache.lock()
-        && let Some(image) = cache.get(id)
-    {
-        return Some(image.clone());
-    }
-
-    None
-}
-
-pub fn insert_static_image(id: &str, image: Image) {
-    let cache = IMAGE_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-
-    if let Ok(mut cache) = cache.lock() {
-        cache.insert(id.to_string(), image);
-    }
-}
-
-pub fn insert_stream_image(id: &str, image: Image) {
-    let cache = STREAM_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-
-    if let Ok(mut cache) = cache.lock() {
-        cache.insert(id.to_string(), image);
-    }
-}
-
-pub fn remove_stream_image(id: &str) {
-    let cache = STREAM_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-
-    if let Ok(mut cache) = cache.lock() {
-        cache.remove(id);
-    }
-}
-
 pub fn decode_texture_image(
     format: &str,
     width: u32,
@@ -1224,7 +1771,21 @@ struct DrawState {
     font_size: f32,
     text_align: TextAlign,
     text_base: TextBase,
-    stack: Vec<DrawStateSnapshot>,
+    text_draw_mode: TextDrawMode,
+    text_decoration: TextDecoration,
+    letter_spacing: f32,
+    text_wrap: Option<TextWrap>,
+    blend_mode: BlendMode,
+    drop_shadow: Option<DropShadow>,
+    blur: Option<Blur>,
+    backdrop_blur: Option<Blur>,
+    stack: Vec<SavedState>,
+}
+
+/// State saved by a push, and the layer it opened, if any.
+struct SavedState {
+    snapshot: DrawStateSnapshot,
+    layer: Option<Layer>,
 }
 
 impl Default for DrawState {
@@ -1243,6 +1804,14 @@ impl Default for DrawState {
             font_size: Self::DEFAULT_FONT_SIZE,
             text_align: TextAlign::Left,
             text_base: TextBase::Alphabetic,
+            text_draw_mode: TextDrawMode::Fill,
+            text_decoration: TextDecoration::default(),
+            letter_spacing: 0.0,
+            text_wrap: None,
+            blend_mode: BlendMode::SrcOver,
+            drop_shadow: None,
+            blur: None,
+            backdrop_blur: None,
             stack: Vec::new(),
         }
     }
@@ -1251,8 +1820,13 @@ impl Default for DrawState {
 impl DrawState {
     const DEFAULT_FONT_SIZE: f32 = 20.0;
 
-    fn push(&mut self) {
-        self.stack.push(DrawStateSnapshot {
+    fn push(&mut self, layer: Option<Layer>) {
+        let snapshot = self.snapshot();
+        self.stack.push(SavedState { snapshot, layer });
+    }
+
+    fn snapshot(&self) -> DrawStateSnapshot {
+        DrawStateSnapshot {
             fill_color: self.fill_color,
             fill_shader: self.fill_shader.clone(),
             stroke_color: self.stroke_color,
@@ -1266,18 +1840,86 @@ impl DrawState {
             font_size: self.font_size,
             text_align: self.text_align,
             text_base: self.text_base,
-        });
+            text_draw_mode: self.text_draw_mode,
+            text_decoration: self.text_decoration,
+            letter_spacing: self.letter_spacing,
+            text_wrap: self.text_wrap,
+            blend_mode: self.blend_mode,
+            drop_shadow: self.drop_shadow,
+            blur: self.blur,
+            backdrop_blur: self.backdrop_blur,
+        }
+    }
+
+    /// Inherited style a recorded picture depends on. Shaders and open paths can't be
+    /// compared, so scripts entered with either are replayed op by op instead.
+    fn cache_key(&self) -> Option<StyleKey> {
+        if self.fill_shader.is_some() || self.stroke_shader.is_some() || self.path.is_some() {
+            return None;
+        }
+        Some(StyleKey {
+            fill_color: self.fill_color,
+            stroke_color: self.stroke_color,
+            stroke_width: self.stroke_width,
+            stroke_cap: self.stroke_cap,
+            stroke_join: self.stroke_join,
+            stroke_miter_limit: self.stroke_miter_limit,
+            font_id: self.font_id.clone(),
+            font_size: self.font_size,
+            text_align: self.text_align,
+            text_base: self.text_base,
+            text_draw_mode: self.text_draw_mode,
+            text_decoration: self.text_decoration,
+            letter_spacing: self.letter_spacing,
+            text_wrap: self.text_wrap,
+            blend_mode: self.blend_mode,
+            drop_shadow: self.drop_shadow,
+            blur: self.blur,
+            backdrop_blur: self.backdrop_blur,
+        })
     }
 
     fn pop(&mut self) {
-        let snapshot = self.stack.pop().unwrap_or_default();
-        self.apply_snapshot(snapshot);
+        let Some(saved) = self.stack.pop() else {
+            self.apply_snapshot(DrawStateSnapshot::default());
+            return;
+        };
+        self.apply_snapshot(saved.snapshot);
+        // A backdrop blur belongs to the layer it was set for.
+        if saved.layer.is_some() {
+            self.backdrop_blur = None;
+        }
+    }
+
+    /// Restores the last saved state and saves it again, returning the layer to reopen.
+    fn pop_push(&mut self) -> Option<Layer> {
+        let saved = self.stack.pop().unwrap_or_else(|| SavedState {
+            snapshot: DrawStateSnapshot::default(),
+            layer: None,
+        });
+        self.apply_snapshot(saved.snapshot.clone());
+        let layer = saved.layer;
+        self.stack.push(saved);
+        layer
     }
 
-    fn pop_push(&mut self) {
-        let snapshot = self.stack.pop().unwrap_or_default();
-        self.apply_snapshot(snapshot.clone());
-        self.stack.push(snapshot);
+    /// Image filter for the current blur and drop shadow; the shadow is cast by the
+    /// blurred drawing.
+    fn image_filter(&self) -> Option<ImageFilter> {
+        let blur = self.blur.and_then(|blur| blur.filter(None));
+        match self.drop_shadow {
+            Some(shadow) => shadow.filter(blur),
+            None => blur,
+        }
+    }
+
+    /// Starts drawing into a layer that was just saved with the current blend mode and
+    /// effects; its contents draw without them.
+    fn enter_layer(&mut self) {
+        self.blend_mode = BlendMode::SrcOver;
+        self.drop_shadow = None;
+        self.blur = None;
+        self.backdrop_blur = None;
     }
 
     fn can_pop(&self) -> bool {
@@ -1298,23 +1940,14 @@ impl DrawState {
         self.font_size = snapshot.font_size;
         self.text_align = snapshot.text_align;
         self.text_base = snapshot.text_base;
-    }
-
-    fn text_offsets(&self, text: &str, font: &Font, paint: &Paint) -> (f32, f32) {
-        let (width, _bounds) = font.measure_str(text, Some(paint));
-        let metrics = font.metrics().1;
-        let dx = match self.text_align {
-            TextAlign::Left => 0.0,
-            TextAlign::Center => -width / 2.0,
-            TextAlign::Right => -width,
-        };
-        let dy = match self.text_base {
-            TextBase::Top => -metrics.ascent,
-            TextBase::Middle => -(metrics.ascent + metrics.descent) / 2.0,
-            TextBase::Alphabetic => 0.0,
-            TextBase::Bottom => -metrics.descent,
-        };
-        (dx, dy)
+        self.text_draw_mode = snapshot.text_draw_mode;
+        self.text_decoration = snapshot.text_decoration;
+        self.letter_spacing = snapshot.letter_spacing;
+        self.text_wrap = snapshot.text_wrap;
+        self.blend_mode = snapshot.blend_mode;
+        self.drop_shadow = snapshot.drop_shadow;
+        self.blur = snapshot.blur;
+        self.backdrop_blur = snapshot.backdrop_blur;
     }
 }
 
@@ -1333,6 +1966,14 @@ struct DrawStateSnapshot {
     font_size: f32,
     text_align: TextAlign,
     text_base: TextBase,
+    text_draw_mode: TextDrawMode,
+    text_decoration: TextDecoration,
+    letter_spacing: f32,
+    text_wrap: Option<TextWrap>,
+    blend_mode: BlendMode,
+    drop_shadow: Option<DropShadow>,
+    blur: Option<Blur>,
+    backdrop_blur: Option<Blur>,
 }
 
 impl Default for DrawStateSnapshot {
@@ -1351,21 +1992,303 @@ impl Default for DrawStateSnapshot {
             font_size: DrawState::DEFAULT_FONT_SIZE,
             text_align: TextAlign::Left,
             text_base: TextBase::Alphabetic,
+            text_draw_mode: TextDrawMode::Fill,
+            text_decoration: TextDecoration::default(),
+            letter_spacing: 0.0,
+            text_wrap: None,
+            blend_mode: BlendMode::SrcOver,
+            drop_shadow: None,
+            blur: None,
+            backdrop_blur: None,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+struct StyleKey {
+    fill_color: Color,
+    stroke_color: Color,
+    stroke_width: f32,
+    stroke_cap: PaintCap,
+    stroke_join: PaintJoin,
+    stroke_miter_limit: f32,
+    font_id: Option<String>,
+    font_size: f32,
+    text_align: TextAlign,
+    text_base: TextBase,
+    text_draw_mode: TextDrawMode,
+    text_decoration: TextDecoration,
+    letter_spacing: f32,
+    text_wrap: Option<TextWrap>,
+    blend_mode: BlendMode,
+    drop_shadow: Option<DropShadow>,
+    blur: Option<Blur>,
+    backdrop_blur: Option<Blur>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TextAlign {
     Left,
     Center,
     Right,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+impl TextAlign {
+    /// Fraction of a line's width that lies left of the text's x position.
+    pub fn anchor(self) -> f32 {
+        match self {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => 0.5,
+            TextAlign::Right => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TextBase {
     Top,
     Middle,
     Alphabetic,
     Bottom,
 }
+
+/// Offscreen layer opened by `PushLayer`. Everything drawn until the matching pop is
+/// composited as one image with `alpha`, so overlapping children fade without seams.
+/// The layer is composited with the blend mode, blur and drop shadow current when it was
+/// pushed, and starts from the backdrop blurred by the current backdrop blur. Its
+/// contents start out drawing with `SrcOver` and no effects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layer {
+    pub alpha: f32,
+    /// Local-space hint for the area drawn into the layer; drawing outside it is clipped.
+    pub bounds: Option<Rect>,
+}
+
+impl Layer {
+    fn save(&self, canvas: &skia_safe::Canvas, draw_state: &DrawState) {
+        let filter = draw_state.image_filter();
+        let backdrop = draw_state.backdrop_blur.and_then(|blur| blur.filter(None));
+        if draw_state.blend_mode == BlendMode::SrcOver && filter.is_none() && backdrop.is_none() {
+            canvas.save_layer_alpha_f(self.bounds, self.alpha);
+            return;
+        }
+        let mut paint = Paint::default();
+        paint
+            .set_alpha_f(self.alpha)
+            .set_blend_mode(draw_state.blend_mode)
+            .set_image_filter(filter);
+        let mut rec = SaveLayerRec::default().paint(&paint);
+        if let Some(bounds) = self.bounds.as_ref() {
+            rec = rec.bounds(bounds);
+        }
+        if let Some(backdrop) = backdrop.as_ref() {
+            rec = rec.backdrop(backdrop);
+        }
+        canvas.save_layer(&rec);
+    }
+}
+
+/// Shadow cast by what's drawn, offset by `dx`/`dy` and blurred by `sigma_x`/`sigma_y`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DropShadow {
+    pub dx: f32,
+    pub dy: f32,
+    pub sigma_x: f32,
+    pub sigma_y: f32,
+    pub color: Color,
+}
+
+impl DropShadow {
+    fn filter(&self, input: Option<ImageFilter>) -> Option<ImageFilter> {
+        image_filters::drop_shadow(
+            Vector::new(self.dx, self.dy),
+            (self.sigma_x, self.sigma_y),
+            self.color,
+            None,
+            input,
+            CropRect::default(),
+        )
+    }
+}
+
+/// Gaussian blur with standard deviations `sigma_x` and `sigma_y`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Blur {
+    pub sigma_x: f32,
+    pub sigma_y: f32,
+}
+
+impl Blur {
+    fn filter(&self, input: Option<ImageFilter>) -> Option<ImageFilter> {
+        image_filters::blur(
+            (self.sigma_x, self.sigma_y),
+            None,
+            input,
+            CropRect::default(),
+        )
+    }
+}
+
+/// Gradient set by `FillGradient` or `StrokeGradient`. `stops` pairs each color with its
+/// position along the gradient, from 0 to 1 and never decreasing, and there are at least
+/// two. `local_matrix` maps the gradient's geometry into the space it's drawn in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    pub stops: Vec<(f32, Color)>,
+    pub tile_mode: TileMode,
+    pub local_matrix: Option<Matrix>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientKind {
+    Linear {
+        start: Point,
+        end: Point,
+    },
+    /// Circles around `center`; a positive `inner_radius` starts the gradient there.
+    Radial {
+        center: Point,
+        inner_radius: f32,
+        outer_radius: f32,
+    },
+    /// Interpolates between two circles, as for spotlights and cones.
+    Conical {
+        start: Point,
+        start_radius: f32,
+        end: Point,
+        end_radius: f32,
+    },
+    /// Sweeps clockwise around `center` from `start_angle` to `end_angle`, in degrees
+    /// from the positive x axis.
+    Sweep {
+        center: Point,
+        start_angle: f32,
+        end_angle: f32,
+    },
+}
+
+impl Gradient {
+    fn shader(&self) -> Option<Shader> {
+        let colors: Vec<Color> = self.stops.iter().map(|(_, color)| *color).collect();
+        let positions: Vec<f32> = self.stops.iter().map(|(position, _)| *position).collect();
+        let colors = colors.as_slice();
+        let positions = Some(positions.as_slice());
+        let local_matrix = self.local_matrix.as_ref();
+        match self.kind {
+            GradientKind::Linear { start, end } => Shader::linear_gradient(
+                (start, end),
+                colors,
+                positions,
+                self.tile_mode,
+                None,
+                local_matrix,
+            ),
+            GradientKind::Radial {
+                center,
+                inner_radius,
+                outer_radius,
+            } => radial_shader(
+                center,
+                inner_radius,
+                outer_radius,
+                colors,
+                positions,
+                self.tile_mode,
+                local_matrix,
+            ),
+            GradientKind::Conical {
+                start,
+                start_radius,
+                end,
+                end_radius,
+            } => Shader::two_point_conical_gradient(
+                start,
+                start_radius,
+                end,
+                end_radius,
+                colors,
+                positions,
+                self.tile_mode,
+                None,
+                local_matrix,
+            ),
+            GradientKind::Sweep {
+                center,
+                start_angle,
+                end_angle,
+            } => Shader::sweep_gradient(
+                center,
+                colors,
+                positions,
+                self.tile_mode,
+                (start_angle, end_angle),
+                None,
+                local_matrix,
+            ),
+        }
+    }
+}
+
+/// Whether `DrawText` fills the glyphs, strokes their outlines with the stroke paint, or
+/// both, filling first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextDrawMode {
+    Fill,
+    Stroke,
+    FillStroke,
+}
+
+impl TextDrawMode {
+    fn fills(self) -> bool {
+        matches!(self, TextDrawMode::Fill | TextDrawMode::FillStroke)
+    }
+
+    fn strokes(self) -> bool {
+        matches!(self, TextDrawMode::Stroke | TextDrawMode::FillStroke)
+    }
+}
+
+/// Box `DrawText` fits its lines into. The box is `max_width` wide and anchored at the
+/// text's x position like a line with the current `TextAlign`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextWrap {
+    pub max_width: f32,
+    pub mode: TextWrapMode,
+    pub overflow: TextOverflow,
+    /// Lines kept when wrapping, or 0 for no limit. With `Ellipsis` the last kept line
+    /// ends in an ellipsis; without a limit every line is truncated instead of wrapped.
+    pub max_lines: u16,
+}
+
+impl TextWrap {
+    fn clip_rect(&self, align: TextAlign) -> Rect {
+        // Only horizontal overflow is clipped.
+        const UNBOUNDED: f32 = 1.0e6;
+        let left = -self.max_width * align.anchor();
+        Rect::new(left, -UNBOUNDED, left + self.max_width, UNBOUNDED)
+    }
+}
+
+/// Where lines may break when they don't fit the box: only at `\n`, between words, or
+/// between any two characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextWrapMode {
+    None,
+    Word,
+    Char,
+}
+
+/// How text that still doesn't fit the box is cut off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextOverflow {
+    Clip,
+    Ellipsis,
+}
+
+/// Lines drawn through `DrawText` output, painted like the glyphs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub strikethrough: bool,
+}
@@ -0,0 +1,1077 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use skia_safe::{
+    Font, FontArguments, FontHinting, FontMetrics, FontMgr, FourByteTag, PixelGeometry, Point,
+    Rect, SurfaceProps, SurfacePropsFlags, TextBlob, TextBlobBuilder, Typeface, TypefaceId,
+    font::Edging,
+    font_arguments::{VariationPosition, variation_position},
+    textlayout::{
+        FontCollection, ParagraphBuilder, ParagraphStyle, StrutStyle, TextStyle,
+        TypefaceFontProvider,
+    },
+};
+
+use crate::assets::AssetCache;
+use crate::renderer::{TextAlign, TextBase, TextDecoration, TextOverflow, TextWrap, TextWrapMode};
+
+/// Family name the default typeface is registered under.
+const DEFAULT_FAMILY: &str = "scenic-default";
+
+/// Shaped text, positioned so the first line's alphabetic baseline is at y = 0 and each
+/// line is aligned against x = 0.
+pub struct TextLayout {
+    pub blob: Option<TextBlob>,
+    /// Runs from color fonts (COLR, CBDT, sbix), drawn in their own colors.
+    pub color_blob: Option<TextBlob>,
+    /// Advance width of the longest line.
+    pub width: f32,
+    pub line_widths: Vec<f32>,
+    /// Left end of each line's baseline.
+    pub line_origins: Vec<Point>,
+    /// Union of the glyph outlines' bounds.
+    pub bounds: Rect,
+    /// Distance from the first line's baseline to the last one's.
+    pub last_baseline: f32,
+    /// Ascent (negative), descent and leading of the primary font.
+    pub ascent: f32,
+    pub descent: f32,
+    pub leading: f32,
+    pub underline: LineMetrics,
+    pub strikethrough: LineMetrics,
+}
+
+/// Placement of a decoration line: `offset` from the baseline to the line's top edge
+/// (negative is above) and its `thickness`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineMetrics {
+    pub offset: f32,
+    pub thickness: f32,
+}
+
+impl TextLayout {
+    /// Offset that puts the block's `base` at the origin; lines are already aligned
+    /// horizontally.
+    pub fn offset(&self, base: TextBase) -> Point {
+        let dy = match base {
+            TextBase::Top => -self.ascent,
+            TextBase::Middle => -(self.ascent + self.last_baseline + self.descent) / 2.0,
+            TextBase::Alphabetic => 0.0,
+            TextBase::Bottom => -(self.last_baseline + self.descent),
+        };
+        Point::new(0.0, dy)
+    }
+
+    /// Rectangles for `decoration`, spanning each line's advance.
+    pub fn decoration_rects(&self, decoration: TextDecoration) -> Vec<Rect> {
+        let mut lines = Vec::new();
+        if decoration.underline {
+            lines.push(self.underline);
+        }
+        if decoration.strikethrough {
+            lines.push(self.strikethrough);
+        }
+        let mut rects = Vec::new();
+        for (width, origin) in self.line_widths.iter().zip(&self.line_origins) {
+            for line in &lines {
+                rects.push(Rect::from_xywh(
+                    origin.x,
+                    origin.y + line.offset,
+                    *width,
+                    line.thickness,
+                ));
+            }
+        }
+        rects
+    }
+}
+
+/// Rasterization settings applied to every font the renderer draws with. The defaults
+/// match a plain `Font::new`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontRendering {
+    pub hinting: FontHinting,
+    pub subpixel: bool,
+    pub edging: Edging,
+    pub embolden: bool,
+}
+
+impl Default for FontRendering {
+    fn default() -> Self {
+        Self {
+            hinting: FontHinting::Normal,
+            subpixel: false,
+            edging: Edging::AntiAlias,
+            embolden: false,
+        }
+    }
+}
+
+impl FontRendering {
+    pub fn parse(
+        hinting: &str,
+        subpixel: bool,
+        edging: &str,
+        embolden: bool,
+    ) -> Result<Self, String> {
+        let hinting = match hinting {
+            "none" => FontHinting::None,
+            "slight" => FontHinting::Slight,
+            "normal" => FontHinting::Normal,
+            "full" => FontHinting::Full,
+            other => return Err(format!("unsupported font hinting: {other}")),
+        };
+        let edging = match edging {
+            "alias" => Edging::Alias,
+            "antialias" => Edging::AntiAlias,
+            "subpixel_antialias" => Edging::SubpixelAntiAlias,
+            other => return Err(format!("unsupported font edging: {other}")),
+        };
+        Ok(Self {
+            hinting,
+            subpixel,
+            edging,
+            embolden,
+        })
+    }
+
+    pub fn apply(&self, font: &mut Font) {
+        font.set_hinting(self.hinting)
+            .set_subpixel(self.subpixel)
+            .set_edging(self.edging)
+            .set_embolden(self.embolden);
+    }
+
+    /// Surface properties for drawing with these settings. LCD edging only takes effect
+    /// on surfaces with a known subpixel layout, assumed to be horizontal RGB.
+    pub fn surface_props(&self) -> SurfaceProps {
+        let geometry = match self.edging {
+            Edging::SubpixelAntiAlias => PixelGeometry::RGBH,
+            _ => PixelGeometry::Unknown,
+        };
+        SurfaceProps::new(SurfacePropsFlags::empty(), geometry)
+    }
+}
+
+/// Text laid out by `TextEngine::prepare`, drawn at `offset`.
+pub struct PreparedText {
+    pub layout: TextLayout,
+    pub offset: Point,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TextKey {
+    font_id: Option<String>,
+    size: u32,
+    text: String,
+    align: TextAlign,
+    base: TextBase,
+    letter_spacing: u32,
+    wrap: Option<(u32, TextWrapMode, TextOverflow, u16)>,
+}
+
+struct CachedText {
+    text: PreparedText,
+    last_used: u64,
+}
+
+/// Prepared text kept across frames so unchanged `DrawText` ops skip shaping.
+struct TextCache {
+    entries: HashMap<TextKey, CachedText>,
+    /// Entries kept before texts not drawn in the previous frame are evicted.
+    capacity: usize,
+    frame: u64,
+    font_generation: u64,
+}
+
+impl Default for TextCache {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity: MAX_CACHED_TEXTS,
+            frame: 0,
+            font_generation: 0,
+        }
+    }
+}
+
+impl TextCache {
+    fn begin_frame(&mut self, font_generation: u64) {
+        // Fonts or the fallback list changed; any entry may now shape differently.
+        if font_generation != self.font_generation {
+            self.entries.clear();
+            self.font_generation = font_generation;
+        }
+        if self.entries.len() > self.capacity {
+            let previous = self.frame;
+            self.entries.retain(|_, entry| entry.last_used >= previous);
+        }
+        self.frame += 1;
+    }
+}
+
+const MAX_CACHED_TEXTS: usize = 4096;
+
+/// A `TextEngine` that any thread may use, one at a time; each driver keeps one for the
+/// `measure_text` NIF.
+#[derive(Default)]
+pub struct SharedTextEngine(Mutex<TextEngine>);
+
+// SAFETY: the engine's Skia objects are only reachable through the mutex, so they are
+// used by one thread at a time. Skia reference counts are atomic and these objects keep
+// no thread-local state; the typefaces they share with the asset cache are `Send + Sync`.
+unsafe impl Send for SharedTextEngine {}
+unsafe impl Sync for SharedTextEngine {}
+
+impl SharedTextEngine {
+    pub fn with<R>(&self, f: impl FnOnce(&mut TextEngine) -> R) -> Result<R, String> {
+        let mut engine = self
+            .0
+            .lock()
+            .map_err(|_| "text engine lock poisoned".to_string())?;
+        Ok(f(&mut engine))
+    }
+}
+
+/// Shapes text with HarfBuzz and resolves bidi runs through Skia's paragraph layout.
+///
+/// Asset typefaces are registered on first use with their font id as the family name.
+/// The engine is not `Send`; each render thread owns its own.
+pub struct TextEngine {
+    collection: FontCollection,
+    provider: TypefaceFontProvider,
+    registered: HashMap<String, Typeface>,
+    system_fonts: bool,
+    color_typefaces: HashMap<TypefaceId, bool>,
+    cache: TextCache,
+}
+
+impl Default for TextEngine {
+    fn default() -> Self {
+        let provider = TypefaceFontProvider::new();
+        let mut collection = FontCollection::new();
+        collection.set_asset_font_manager(Some(FontMgr::from(provider.clone())));
+        collection.disable_font_fallback();
+        Self {
+            collection,
+            provider,
+            registered: HashMap::new(),
+            system_fonts: false,
+            color_typefaces: HashMap::new(),
+            cache: TextCache::default(),
+        }
+    }
+}
+
+impl TextEngine {
+    /// Starts a frame, evicting prepared text when the cache is over capacity or when
+    /// `font_generation` shows that fonts changed.
+    pub fn begin_frame(&mut self, font_generation: u64) {
+        if font_generation != self.cache.font_generation {
+            // Fonts may have been removed; the ones still in use register again on demand.
+            self.forget_typefaces();
+        }
+        self.cache.begin_frame(font_generation);
+    }
+
+    /// Like `layout`, but reuses the text prepared for an identical op in an earlier
+    /// frame and computes the offset for `base`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare(
+        &mut self,
+        assets: &AssetCache,
+        font_id: Option<&str>,
+        size: f32,
+        text: &str,
+        align: TextAlign,
+        base: TextBase,
+        letter_spacing: f32,
+        wrap: Option<TextWrap>,
+    ) -> Option<&PreparedText> {
+        let key = TextKey {
+            font_id: font_id.map(str::to_string),
+            size: size.to_bits(),
+            text: text.to_string(),
+            align,
+            base,
+            letter_spacing: letter_spacing.to_bits(),
+            wrap: wrap.map(|wrap| {
+                let width = wrap.max_width.to_bits();
+                (width, wrap.mode, wrap.overflow, wrap.max_lines)
+            }),
+        };
+        let frame = self.cache.frame;
+        if !self.cache.entries.contains_key(&key) {
+            let layout = self.layout(assets, font_id, size, text, align, letter_spacing, wrap)?;
+            let offset = layout.offset(base);
+            let text = PreparedText { layout, offset };
+            self.cache
+                .entries
+                .insert(key.clone(), CachedText { text, last_used: 0 });
+        }
+        let entry = self.cache.entries.get_mut(&key)?;
+        entry.last_used = frame;
+        Some(&entry.text)
+    }
+
+    /// Shapes `text` in the font `font_id`, or the default font when `None`, breaking lines
+    /// at `\n` and aligning each line with `align`. `letter_spacing` is added after every
+    /// character, and `wrap` fits the lines to a maximum width. Characters the font lacks
+    /// are taken from the driver's fallback fonts. Returns `None` when the font isn't
+    /// loaded.
+    #[allow(clippy::too_many_arguments)]
+    pub fn layout(
+        &mut self,
+        assets: &AssetCache,
+        font_id: Option<&str>,
+        size: f32,
+        text: &str,
+        align: TextAlign,
+        letter_spacing: f32,
+        wrap: Option<TextWrap>,
+    ) -> Option<TextLayout> {
+        let (family, typeface) = match font_id {
+            Some(font_id) => (font_id, assets.typeface(font_id)?),
+            None => (DEFAULT_FAMILY, default_typeface()?),
+        };
+        let (_, metrics) = Font::new(typeface.clone(), size).metrics();
+        self.register(family, typeface);
+
+        // HarfBuzz shapes each run with the first family that has its glyphs.
+        let fallback = assets.font_fallback();
+        let mut families = vec![family.to_string()];
+        for font_id in fallback.font_ids {
+            if families.contains(&font_id) {
+                continue;
+            }
+            if let Some(typeface) = assets.typeface(&font_id) {
+                self.register(&font_id, typeface);
+                families.push(font_id);
+            }
+        }
+        self.use_system_fonts(fallback.system_fonts);
+
+        let mut text_style = TextStyle::new();
+        text_style.set_font_families(&families);
+        text_style.set_font_size(size);
+        text_style.set_letter_spacing(letter_spacing);
+        // Every line gets the primary font's height, whatever fallback fonts it uses.
+        let mut strut_style = StrutStyle::new();
+        strut_style
+            .set_font_families(&[family])
+            .set_font_size(size)
+            .set_strut_enabled(true)
+            .set_force_strut_height(true);
+        let mut paragraph_style = ParagraphStyle::new();
+        paragraph_style
+            .set_text_style(&text_style)
+            .set_strut_style(strut_style)
+            .set_apply_rounding_hack(false);
+
+        let mut text = Cow::Borrowed(text);
+        let mut layout_width = f32::INFINITY;
+        if let Some(wrap) = wrap {
+            // Unwrapped lines are only laid out narrower to place the ellipsis; clipped
+            // ones are cut off when drawn.
+            if wrap.mode != TextWrapMode::None || wrap.overflow == TextOverflow::Ellipsis {
+                layout_width = wrap.max_width;
+            }
+            if wrap.mode == TextWrapMode::Char {
+                text = Cow::Owned(break_between_chars(&text));
+            }
+            if wrap.mode != TextWrapMode::None && wrap.max_lines > 0 {
+                paragraph_style.set_max_lines(wrap.max_lines as usize);
+            }
+            if wrap.overflow == TextOverflow::Ellipsis {
+                paragraph_style.set_ellipsis(ELLIPSIS);
+            }
+        }
+
+        let mut builder = ParagraphBuilder::new(&paragraph_style, self.collection.clone());
+        builder.push_style(&text_style);
+        builder.add_text(text.as_ref());
+        let mut paragraph = builder.build();
+        paragraph.layout(layout_width);
+
+        let lines = paragraph.get_line_metrics();
+        let line_widths: Vec<f32> = lines.iter().map(|line| line.width as f32).collect();
+        let baseline = lines.first().map_or(0.0, |line| line.baseline as f32);
+        let last_baseline = lines.last().map_or(0.0, |line| line.baseline as f32) - baseline;
+        let align = align.anchor();
+        let line_origins = lines
+            .iter()
+            .zip(&line_widths)
+            .map(|(line, width)| Point::new(-width * align, line.baseline as f32 - baseline))
+            .collect();
+
+        let rendering = assets.font_rendering();
+        let color_typefaces = &mut self.color_typefaces;
+        let mut blob_builder = TextBlobBuilder::new();
+        let mut color_builder = TextBlobBuilder::new();
+        let mut bounds = Rect::new_empty();
+        paragraph.visit(|line, info| {
+            let Some(info) = info else {
+                return;
+            };
+            let line_width = line_widths.get(line).copied().unwrap_or_default();
+            let origin = info.origin() - Point::new(line_width * align, baseline);
+            let mut font = info.font().clone();
+            rendering.apply(&mut font);
+            let typeface = font.typeface();
+            let color = *color_typefaces
+                .entry(typeface.unique_id())
+                .or_insert_with(|| is_color_typeface(&typeface));
+            let builder = if color {
+                // Bitmap and layered glyphs don't embolden cleanly.
+                font.set_embolden(false);
+                &mut color_builder
+            } else {
+                &mut blob_builder
+            };
+            let (glyphs, positions) = builder.alloc_run_pos(&font, info.count(), None);
+            glyphs.copy_from_slice(info.glyphs());
+            for (position, shaped) in positions.iter_mut().zip(info.positions()) {
+                *position = origin + *shaped;
+            }
+
+            let mut glyph_bounds = vec![Rect::new_empty(); info.count()];
+            font.get_bounds(info.glyphs(), &mut glyph_bounds, None);
+            for (glyph, position) in glyph_bounds.iter().zip(positions.iter()) {
+                bounds.join(glyph.with_offset(*position));
+            }
+        });
+
+        Some(TextLayout {
+            blob: blob_builder.make(),
+            color_blob: color_builder.make(),
+            width: paragraph.longest_line(),
+            line_widths,
+            line_origins,
+            bounds,
+            last_baseline,
+            ascent: metrics.ascent,
+            descent: metrics.descent,
+            leading: metrics.leading,
+            underline: underline_metrics(&metrics, size),
+            strikethrough: strikethrough_metrics(&metrics, size),
+        })
+    }
+
+    /// Makes `typeface` resolvable as `family`. Font ids are content hashes, so a family
+    /// only changes typeface when a font is reloaded; the provider is rebuilt then.
+    fn register(&mut self, family: &str, typeface: Typeface) {
+        match self.registered.get(family) {
+            Some(current) if current.unique_id() == typeface.unique_id() => return,
+            Some(_) => {
+                self.registered.insert(family.to_string(), typeface);
+                self.provider = TypefaceFontProvider::new();
+                for (family, typeface) in &self.registered {
+                    self.provider
+                        .register_typeface(typeface.clone(), family.as_str());
+                }
+                self.collection
+                    .set_asset_font_manager(Some(FontMgr::from(self.provider.clone())));
+            }
+            None => {
+                self.provider.register_typeface(typeface.clone(), family);
+                self.registered.insert(family.to_string(), typeface);
+            }
+        }
+        self.collection.clear_caches();
+    }
+
+    fn forget_typefaces(&mut self) {
+        if self.registered.is_empty() {
+            return;
+        }
+        self.registered.clear();
+        self.color_typefaces.clear();
+        self.provider = TypefaceFontProvider::new();
+        self.collection
+            .set_asset_font_manager(Some(FontMgr::from(self.provider.clone())));
+        self.collection.clear_caches();
+    }
+
+    /// Lets the collection look up characters no registered family covers in the system
+    /// fonts.
+    fn use_system_fonts(&mut self, enabled: bool) {
+        if self.system_fonts == enabled {
+            return;
+        }
+        self.system_fonts = enabled;
+        if enabled {
+            self.collection
+                .set_default_font_manager(FontMgr::new(), None::<&str>);
+            self.collection.enable_font_fallback();
+        } else {
+            self.collection
+                .set_default_font_manager(None::<FontMgr>, None::<&str>);
+            self.collection.disable_font_fallback();
+        }
+        self.collection.clear_caches();
+    }
+}
+
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Inserts a zero-width space after every character so lines can break anywhere. Marks
+/// and joiners stay attached to the character before them.
+fn break_between_chars(text: &str) -> String {
+    let mut broken = String::with_capacity(text.len() * 2);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        broken.push(c);
+        let Some(&next) = chars.peek() else {
+            break;
+        };
+        if c.is_whitespace() || c == '\u{200D}' || next.is_whitespace() || is_attached(next) {
+            continue;
+        }
+        broken.push('\u{200B}');
+    }
+    broken
+}
+
+/// Combining marks, joiners, variation selectors and emoji modifiers, which belong to the
+/// preceding character's cluster.
+fn is_attached(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{200C}'..='\u{200D}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FE20}'..='\u{FE2F}'
+            | '\u{1F3FB}'..='\u{1F3FF}'
+            | '\u{E0100}'..='\u{E01EF}'
+    )
+}
+
+/// Skia reports the distance from the baseline to the underline's top edge, falling
+/// back to a line just below the baseline when the font has no `post` metrics.
+fn underline_metrics(metrics: &FontMetrics, size: f32) -> LineMetrics {
+    let thickness = decoration_thickness(metrics.underline_thickness(), size);
+    let offset = metrics.underline_position().unwrap_or(thickness);
+    LineMetrics { offset, thickness }
+}
+
+/// Skia reports the distance from the baseline to the strikeout's bottom edge, falling
+/// back to a line through the middle of the x-height when the font has no `OS/2` metrics.
+fn strikethrough_metrics(metrics: &FontMetrics, size: f32) -> LineMetrics {
+    let thickness = decoration_thickness(metrics.strikeout_thickness(), size);
+    let bottom = metrics
+        .strikeout_position()
+        .unwrap_or(-metrics.x_height / 2.0 + thickness / 2.0);
+    LineMetrics {
+        offset: bottom - thickness,
+        thickness,
+    }
+}
+
+fn decoration_thickness(thickness: Option<f32>, size: f32) -> f32 {
+    thickness.filter(|t| *t > 0.0).unwrap_or(size / 18.0)
+}
+
+/// Whether the typeface has color glyphs from COLR, CBDT or sbix tables, which Skia
+/// draws in their own colors.
+fn is_color_typeface(typeface: &Typeface) -> bool {
+    [
+        ('C', 'O', 'L', 'R'),
+        ('C', 'B', 'D', 'T'),
+        ('s', 'b', 'i', 'x'),
+    ]
+    .into_iter()
+    .any(|(a, b, c, d)| {
+        typeface
+            .get_table_size(*FourByteTag::from_chars(a, b, c, d))
+            .is_some()
+    })
+}
+
+/// Decodes face `face_index` of a font file or collection, instanced at the variation
+/// coordinates in `axes`, such as `("wght", 700.0)`.
+pub fn decode_font(
+    data: &[u8],
+    face_index: usize,
+    axes: &[(String, f32)],
+) -> Result<Typeface, String> {
+    let typeface = FontMgr::new()
+        .new_from_data(data, face_index)
+        .ok_or_else(|| format!("invalid font data or face index: {face_index}"))?;
+    if axes.is_empty() {
+        return Ok(typeface);
+    }
+    let coordinates = axes
+        .iter()
+        .map(|(tag, value)| {
+            Ok(variation_position::Coordinate {
+                axis: font_axis_tag(tag)?,
+                value: *value,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let arguments = FontArguments::new().set_variation_design_position(VariationPosition {
+        coordinates: &coordinates,
+    });
+    typeface
+        .clone_with_arguments(&arguments)
+        .ok_or_else(|| "failed to apply font variation".to_string())
+}
+
+fn font_axis_tag(tag: &str) -> Result<FourByteTag, String> {
+    match tag.as_bytes() {
+        &[a, b, c, d] if tag.is_ascii() => Ok(FourByteTag::from_chars(
+            a as char, b as char, c as char, d as char,
+        )),
+        _ => Err(format!("invalid font axis tag: {tag}")),
+    }
+}
+
+/// Roboto, the Scenic default font, compiled in so text without a font renders the same
+/// on every machine, including ones without system fonts.
+static DEFAULT_FONT: &[u8] = include_bytes!("../../../assets/fonts/roboto.ttf");
+
+pub fn default_typeface() -> Option<Typeface> {
+    static DEFAULT_TYPEFACE: OnceLock<Option<Typeface>> = OnceLock::new();
+    DEFAULT_TYPEFACE
+        .get_or_init(|| decode_font(DEFAULT_FONT, 0, &[]).ok())
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::FontFallback;
+
+    fn roboto_assets() -> AssetCache {
+        let assets = AssetCache::default();
+        let typeface =
+            decode_font(include_bytes!("../../../assets/fonts/roboto.ttf"), 0, &[]).unwrap();
+        assets.insert_typeface("roboto", typeface).unwrap();
+        assets
+    }
+
+    #[test]
+    fn layout_shapes_text_from_asset_fonts() {
+        let assets = roboto_assets();
+        let mut engine = TextEngine::default();
+
+        let layout = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "Hello",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        assert!(layout.blob.is_some());
+        assert!(layout.color_blob.is_none());
+        assert!(!is_color_typeface(&assets.typeface("roboto").unwrap()));
+        assert!(layout.width > 0.0);
+        assert!(layout.ascent < 0.0 && layout.descent > 0.0);
+
+        let bounds = *layout.blob.unwrap().bounds();
+        assert!(bounds.top < 0.0 && bounds.bottom > 0.0);
+        assert!(layout.bounds.left >= 0.0 && layout.bounds.right <= layout.width + 1.0);
+        assert!(layout.bounds.top >= layout.ascent && layout.bounds.top < 0.0);
+
+        let empty = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        assert!(empty.blob.is_none());
+        assert_eq!(empty.width, 0.0);
+        assert!(
+            engine
+                .layout(
+                    &assets,
+                    Some("missing"),
+                    20.0,
+                    "x",
+                    TextAlign::Left,
+                    0.0,
+                    None,
+                )
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn layout_breaks_lines_and_aligns_each_one() {
+        let assets = roboto_assets();
+        let mut engine = TextEngine::default();
+
+        let single = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "Wide line",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        assert_eq!(single.line_widths.len(), 1);
+        assert_eq!(single.last_baseline, 0.0);
+
+        let layout = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "Wide line\nab",
+                TextAlign::Right,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        assert_eq!(layout.line_widths.len(), 2);
+        assert!(layout.line_widths[1] < layout.line_widths[0]);
+        assert_eq!(layout.width, layout.line_widths[0]);
+        let line_height = layout.descent - layout.ascent;
+        assert!((layout.last_baseline - line_height).abs() < 2.0);
+
+        // Right-aligned lines end at x = 0.
+        let bounds = *layout.blob.unwrap().bounds();
+        assert!(bounds.right <= 1.0);
+        assert!(bounds.left >= -layout.width - 2.0);
+    }
+
+    #[test]
+    fn letter_spacing_and_decorations_follow_each_line() {
+        let assets = roboto_assets();
+        let mut engine = TextEngine::default();
+
+        let plain = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "mmm",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        let spaced = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "mmm",
+                TextAlign::Left,
+                4.0,
+                None,
+            )
+            .expect("layout");
+        assert!((spaced.width - plain.width - 12.0).abs() < 0.5);
+
+        let layout = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "Wide line\nab",
+                TextAlign::Center,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        assert!(layout.underline.offset > 0.0);
+        assert!(layout.underline.thickness > 0.0);
+        assert!(layout.strikethrough.offset < 0.0);
+        assert!(layout.strikethrough.offset > layout.ascent);
+
+        let none = layout.decoration_rects(TextDecoration::default());
+        assert!(none.is_empty());
+
+        let rects = layout.decoration_rects(TextDecoration {
+            underline: true,
+            strikethrough: true,
+        });
+        assert_eq!(rects.len(), 4);
+        let second = rects[2];
+        assert_eq!(second.width(), layout.line_widths[1]);
+        assert!(second.center_x().abs() < 0.01);
+        assert_eq!(second.top, layout.last_baseline + layout.underline.offset);
+    }
+
+    #[test]
+    fn wrapping_fits_lines_to_the_max_width() {
+        let assets = roboto_assets();
+        let mut engine = TextEngine::default();
+        let mut layout = |text: &str, wrap: Option<TextWrap>| {
+            engine
+                .layout(
+                    &assets,
+                    Some("roboto"),
+                    20.0,
+                    text,
+                    TextAlign::Left,
+                    0.0,
+                    wrap,
+                )
+                .expect("layout")
+        };
+        let wrap = |max_width, mode, overflow, max_lines| {
+            Some(TextWrap {
+                max_width,
+                mode,
+                overflow,
+                max_lines,
+            })
+        };
+
+        let ab = layout("ab", None).width;
+        let max_width = layout("ab c", None).width + 0.5;
+
+        let words = layout(
+            "ab cd",
+            wrap(max_width, TextWrapMode::Word, TextOverflow::Clip, 0),
+        );
+        assert_eq!(words.line_widths.len(), 2);
+        assert!((words.line_widths[0] - ab).abs() < 0.5);
+
+        let chars = layout(
+            "ab cd",
+            wrap(max_width, TextWrapMode::Char, TextOverflow::Clip, 0),
+        );
+        assert_eq!(chars.line_widths.len(), 2);
+        assert!(chars.line_widths[0] > ab + 1.0);
+        assert!(chars.line_widths[0] <= max_width);
+
+        // Unwrapped clipped text keeps its full width; it's cut off when drawn.
+        let clipped = layout(
+            "ab cd",
+            wrap(max_width, TextWrapMode::None, TextOverflow::Clip, 0),
+        );
+        assert_eq!(clipped.line_widths.len(), 1);
+        assert!(clipped.width > max_width);
+
+        let truncated = layout(
+            "ab cd ef gh",
+            wrap(max_width, TextWrapMode::None, TextOverflow::Ellipsis, 0),
+        );
+        assert_eq!(truncated.line_widths.len(), 1);
+        assert!(truncated.width <= max_width);
+
+        let limited = layout(
+            "ab cd ef gh",
+            wrap(max_width, TextWrapMode::Word, TextOverflow::Ellipsis, 2),
+        );
+        assert_eq!(limited.line_widths.len(), 2);
+        assert!(limited.line_widths.iter().all(|width| *width <= max_width));
+    }
+
+    #[test]
+    fn char_breaks_keep_marks_with_their_base() {
+        assert_eq!(break_between_chars("ab"), "a\u{200B}b");
+        assert_eq!(break_between_chars("a b"), "a b");
+        assert_eq!(break_between_chars("e\u{301}x"), "e\u{301}\u{200B}x");
+        assert_eq!(
+            break_between_chars("\u{1F469}\u{200D}\u{1F4BB}"),
+            "\u{1F469}\u{200D}\u{1F4BB}"
+        );
+    }
+
+    #[test]
+    fn fallback_fonts_cover_missing_glyphs() {
+        let assets = roboto_assets();
+        let mono = decode_font(
+            include_bytes!("../../../assets/fonts/roboto_mono.ttf"),
+            0,
+            &[],
+        )
+        .unwrap();
+        assets.insert_typeface("roboto_mono", mono).unwrap();
+        let mut engine = TextEngine::default();
+
+        let primary_only = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "mmm",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        assets.set_font_fallback(FontFallback {
+            font_ids: vec!["missing".to_string(), "roboto_mono".to_string()],
+            system_fonts: false,
+        });
+        let with_fallback = engine
+            .layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "mmm",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+            .expect("layout");
+
+        // Roboto has the glyphs, so the fallback font isn't used.
+        assert_eq!(primary_only.width, with_fallback.width);
+        assert_eq!(engine.registered.len(), 2);
+    }
+
+    #[test]
+    fn text_without_a_font_uses_the_embedded_default() {
+        let typeface = default_typeface().expect("default typeface");
+        assert_eq!(typeface.family_name(), "Roboto");
+
+        let mut engine = TextEngine::default();
+        let layout = engine
+            .layout(
+                &AssetCache::default(),
+                None,
+                20.0,
+                "Hello",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        let roboto = engine
+            .layout(
+                &roboto_assets(),
+                Some("roboto"),
+                20.0,
+                "Hello",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+            .expect("layout");
+        assert_eq!(layout.width, roboto.width);
+    }
+
+    #[test]
+    fn prepared_text_is_reused_and_evicted_when_unused() {
+        let assets = roboto_assets();
+        let mut engine = TextEngine::default();
+        engine.cache.capacity = 1;
+
+        let prepare = |engine: &mut TextEngine, text: &str| {
+            engine
+                .prepare(
+                    &assets,
+                    Some("roboto"),
+                    20.0,
+                    text,
+                    TextAlign::Left,
+                    TextBase::Top,
+                    0.0,
+                    None,
+                )
+                .and_then(|prepared| prepared.layout.blob.as_ref().map(|blob| blob.unique_id()))
+                .expect("prepared text")
+        };
+
+        engine.begin_frame(assets.font_generation());
+        let first = prepare(&mut engine, "first");
+        assert_eq!(prepare(&mut engine, "first"), first);
+        prepare(&mut engine, "second");
+
+        // Over capacity: only texts drawn in the previous frame survive.
+        engine.begin_frame(assets.font_generation());
+        prepare(&mut engine, "second");
+        engine.begin_frame(assets.font_generation());
+        assert_eq!(engine.cache.entries.len(), 1);
+        assert_ne!(prepare(&mut engine, "first"), first);
+
+        // Image uploads leave shaped text alone.
+        let image = skia_safe::surfaces::raster_n32_premul((1, 1))
+            .expect("surface")
+            .image_snapshot();
+        assets.insert_static_image("logo", image);
+        engine.begin_frame(assets.font_generation());
+        assert_eq!(engine.cache.entries.len(), 1);
+
+        // A font change drops every entry.
+        let mono = decode_font(
+            include_bytes!("../../../assets/fonts/roboto_mono.ttf"),
+            0,
+            &[],
+        )
+        .unwrap();
+        assets.insert_typeface("roboto_mono", mono).unwrap();
+        engine.begin_frame(assets.font_generation());
+        assert!(engine.cache.entries.is_empty());
+    }
+
+    #[test]
+    fn deleted_fonts_are_unregistered_on_the_next_frame() {
+        let assets = roboto_assets();
+        let engine = SharedTextEngine::default();
+        let layout = |engine: &mut TextEngine| {
+            engine.begin_frame(assets.font_generation());
+            engine.layout(
+                &assets,
+                Some("roboto"),
+                20.0,
+                "hi",
+                TextAlign::Left,
+                0.0,
+                None,
+            )
+        };
+
+        assert!(engine.with(layout).unwrap().is_some());
+        assert_eq!(engine.with(|engine| engine.registered.len()).unwrap(), 1);
+
+        assert!(assets.remove_font("roboto").unwrap());
+        assert!(engine.with(layout).unwrap().is_none());
+        assert!(engine.with(|engine| engine.registered.is_empty()).unwrap());
+    }
+
+    #[test]
+    fn fonts_decode_with_face_index_and_axes() {
+        let data = include_bytes!("../../../assets/fonts/roboto.ttf");
+        assert!(decode_font(data, 0, &[]).is_ok());
+        assert!(decode_font(data, 1, &[]).is_err());
+        assert!(decode_font(data, 0, &[("wght".to_string(), 700.0)]).is_ok());
+        assert_eq!(
+            decode_font(data, 0, &[("weight".to_string(), 700.0)]).err(),
+            Some("invalid font axis tag: weight".to_string())
+        );
+    }
+
+    #[test]
+    fn font_rendering_options_parse() {
+        let rendering = FontRendering::parse("slight", true, "subpixel_antialias", true).unwrap();
+        assert_eq!(rendering.hinting, FontHinting::Slight);
+        assert_eq!(rendering.edging, Edging::SubpixelAntiAlias);
+        assert_eq!(
+            rendering.surface_props().pixel_geometry(),
+            PixelGeometry::RGBH
+        );
+
+        let mut font = Font::default();
+        rendering.apply(&mut font);
+        assert!(font.is_subpixel() && font.is_embolden());
+
+        assert_eq!(
+            FontRendering::parse("heavy", false, "alias", false).err(),
+            Some("unsupported font hinting: heavy".to_string())
+        );
+        assert!(FontRendering::parse("none", false, "lcd", false).is_err());
+    }
+}
@@ -12,7 +12,19 @@ defmodule ScenicDriverSkia.BenchScriptIngest do
     warmup = Keyword.get(opts, :warmup, 100)
 
     renderer =
-      case Native.start("raster", nil, "Scenic Window", false, nil, true, false) do
+      case Native.start(
+             "raster",
+             nil,
+             "Scenic Window",
+             false,
+             nil,
+             true,
+             false,
+             nil,
+             false,
+             nil,
+             nil
+           ) do
         {:ok, renderer} -> renderer
         other -> raise "start returned #{inspect(other)}"
       end
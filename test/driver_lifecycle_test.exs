@@ -24,6 +24,29 @@ defmodule Scenic.Driver.Skia.DriverLifecycleTest do
     assert_receive {:DOWN, ^monitor, :process, _pid, _reason}, 500
   end
 
+  test "reports presented frames to the registered process" do
+    vp = ViewPortHelper.start()
+    renderer = ViewPortHelper.renderer(vp)
+
+    on_exit(fn ->
+      if Process.alive?(vp.pid) do
+        _ = ViewPort.stop(vp)
+      end
+    end)
+
+    :ok = Scenic.Driver.Skia.set_frame_target(renderer, self())
+    {:ok, _} = ViewPort.put_graph(vp, :graph_a, graph_a())
+    assert_receive {:frame_presented, first, first_ns}, 1_000
+
+    {:ok, _} = ViewPort.put_graph(vp, :graph_a, graph_b())
+    assert_receive {:frame_presented, second, second_ns}, 1_000
+
+    assert second > first
+    assert second_ns >= first_ns
+
+    :ok = Scenic.Driver.Skia.set_frame_target(renderer, nil)
+  end
+
   defp graph_a do
     Graph.build()
     |> rect({10, 10}, fill: :red)
@@ -7,6 +7,7 @@ defmodule Scenic.Driver.Skia.DriverOptionsTest do
     assert {:ok, opts} = Skia.validate_opts([])
     assert opts[:backend] == "wayland"
     assert opts[:debug] == false
+    assert opts[:max_fps] == nil
     assert Keyword.get(opts[:window], :title) == "Scenic Window"
     assert Keyword.get(opts[:window], :resizeable) == false
 
@@ -20,6 +21,66 @@ defmodule Scenic.Driver.Skia.DriverOptionsTest do
     assert {:error, %NimbleOptions.ValidationError{}} = Skia.validate_opts(backend: 123)
   end
 
+  test "validate_opts accepts a positive max_fps" do
+    assert {:ok, opts} = Skia.validate_opts(max_fps: 30)
+    assert opts[:max_fps] == 30
+
+    assert {:error, %NimbleOptions.ValidationError{}} = Skia.validate_opts(max_fps: 0)
+  end
+
+  test "validate_opts accepts asset options" do
+    assert {:ok, opts} = Skia.validate_opts([])
+    assert Keyword.get(opts[:assets], :shared_fonts) == false
+
+    assert {:ok, opts} = Skia.validate_opts(assets: [shared_fonts: true])
+    assert Keyword.get(opts[:assets], :shared_fonts) == true
+
+    assert {:ok, opts} = Skia.validate_opts(assets: [image_cache_bytes: 64_000_000])
+    assert Keyword.get(opts[:assets], :image_cache_bytes) == 64_000_000
+
+    assert {:error, %NimbleOptions.ValidationError{}} =
+             Skia.validate_opts(assets: [shared_fonts: "yes"])
+
+    assert {:error, %NimbleOptions.ValidationError{}} =
+             Skia.validate_opts(assets: [image_cache_bytes: 0])
+  end
+
+  test "validate_opts accepts text options" do
+    assert {:ok, opts} = Skia.validate_opts([])
+    assert Keyword.get(opts[:text], :fallback_fonts) == []
+    assert Keyword.get(opts[:text], :system_fonts) == false
+
+    assert {:ok, opts} =
+             Skia.validate_opts(text: [fallback_fonts: [:roboto_mono], system_fonts: true])
+
+    assert Keyword.get(opts[:text], :fallback_fonts) == [:roboto_mono]
+    assert Keyword.get(opts[:text], :system_fonts) == true
+
+    assert {:error, %NimbleOptions.ValidationError{}} =
+             Skia.validate_opts(text: [fallback_fonts: :roboto_mono])
+  end
+
+  test "validate_opts accepts font rasterization options" do
+    assert {:ok, opts} = Skia.validate_opts([])
+    assert Keyword.get(opts[:text], :hinting) == :normal
+    assert Keyword.get(opts[:text], :subpixel) == false
+    assert Keyword.get(opts[:text], :edging) == :antialias
+    assert Keyword.get(opts[:text], :embolden) == false
+
+    assert {:ok, opts} =
+             Skia.validate_opts(
+               text: [hinting: :slight, subpixel: true, edging: :subpixel_antialias]
+             )
+
+    assert Keyword.get(opts[:text], :hinting) == :slight
+    assert Keyword.get(opts[:text], :edging) == :subpixel_antialias
+
+    assert {:error, %NimbleOptions.ValidationError{}} =
+             Skia.validate_opts(text: [hinting: :heavy])
+
+    assert {:error, %NimbleOptions.ValidationError{}} = Skia.validate_opts(text: [edging: :lcd])
+  end
+
   test "validate_opts rejects invalid window options" do
     assert {:error, %NimbleOptions.ValidationError{}} =
              Skia.validate_opts(window: [resizeable: "nope"])
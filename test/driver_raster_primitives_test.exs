@@ -2,9 +2,13 @@ defmodule Scenic.Driver.Skia.RasterPrimitivesTest do
   use ExUnit.Case, async: true
 
   alias Scenic.Driver.Skia.Native
+  alias Scenic.Driver.Skia.TestSupport.ScriptScene
   alias Scenic.Driver.Skia.TestSupport.ViewPort, as: ViewPortHelper
+  alias Scenic.Script
   alias Scenic.ViewPort
 
+  import ScriptScene, only: [op: 2]
+
   defmodule RectScene do
     use Scenic.Scene
     import Scenic.Primitives
@@ -171,6 +175,59 @@ defmodule Scenic.Driver.Skia.RasterPrimitivesTest do
     end
   end
 
+  defmodule NestedScriptScene do
+    use Scenic.Scene
+    import Scenic.Primitives
+    alias Scenic.Script
+
+    def init(scene, _args, _opts) do
+      graph =
+        Scenic.Graph.build()
+        |> script("child", translate: {10, 10})
+
+      script =
+        Script.start()
+        |> Script.fill_color(:red)
+        |> Script.draw_rectangle(20, 20, :fill)
+        |> Script.finish()
+
+      scene = Scenic.Scene.push_script(scene, script, "child")
+      {:ok, Scenic.Scene.push_graph(scene, graph)}
+    end
+  end
+
+  defmodule SideBySideScriptScene do
+    use Scenic.Scene
+    import Scenic.Primitives
+    alias Scenic.Script
+
+    def init(scene, _args, _opts) do
+      graph =
+        Scenic.Graph.build()
+        |> script("left", translate: {0, 0})
+        |> script("right", translate: {40, 0})
+
+      left =
+        Script.start()
+        |> Script.fill_color(:red)
+        |> Script.draw_rectangle(20, 20, :fill)
+        |> Script.finish()
+
+      right =
+        Script.start()
+        |> Script.fill_color(:lime)
+        |> Script.draw_rectangle(20, 20, :fill)
+        |> Script.finish()
+
+      scene =
+        scene
+        |> Scenic.Scene.push_script(left, "left")
+        |> Scenic.Scene.push_script(right, "right")
+
+      {:ok, Scenic.Scene.push_graph(scene, graph)}
+    end
+  end
+
   defmodule LineScene do
     use Scenic.Scene
     import Scenic.Primitives
@@ -747,6 +804,180 @@ defmodule Scenic.Driver.Skia.RasterPrimitivesTest do
     assert pixel_at(frame, width, 35, 35) == {0, 0, 0}
   end
 
+  test "text_draw_mode stroke outlines glyphs and their underline" do
+    renderer =
+      start_script_scene(fn script ->
+        script
+        |> Script.fill_color(:lime)
+        |> Script.stroke_color(:red)
+        |> Script.stroke_width(1)
+        |> Script.font_size(24)
+        |> Script.translate(4, 24)
+        |> op({:text_draw_mode, :stroke})
+        |> op({:text_decoration, [:underline]})
+        |> op({:letter_spacing, 2})
+        |> Script.draw_text("ooo")
+      end)
+
+    {width, _height, frame} =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        any_red?(data, w, 4..50, 8..24)
+      end)
+
+    # The underline sits below the baseline, outlined like the glyphs.
+    assert any_red?(frame, width, 4..50, 26..29)
+
+    # Nothing is filled.
+    refute Enum.any?(0..63, fn x ->
+             Enum.any?(0..63, fn y ->
+               {r, g, _b} = pixel_at(frame, width, x, y)
+               g > 200 and r < 80
+             end)
+           end)
+  end
+
+  test "text_wrap breaks lines at the max width" do
+    renderer =
+      start_script_scene(fn script ->
+        script
+        |> Script.fill_color(:white)
+        |> Script.font_size(12)
+        |> Script.translate(4, 16)
+        |> op({:text_wrap, 40, wrap: :word})
+        |> Script.draw_text("mmm mmm mmm")
+      end)
+
+    {width, _height, frame} =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        any_non_background?(data, w, 4..44, 6..16)
+      end)
+
+    # Each "mmm" gets its own line, and nothing is drawn past the box.
+    assert any_non_background?(frame, width, 4..44, 22..30)
+    assert any_non_background?(frame, width, 4..44, 36..44)
+    refute any_non_background?(frame, width, 46..63, 0..63)
+  end
+
+  test "push_layer fades overlapping children as one group" do
+    renderer =
+      start_script_scene(fn script ->
+        script
+        |> op({:push_layer, 0.5})
+        |> Script.fill_color(:white)
+        |> Script.draw_rectangle(30, 30, :fill)
+        |> Script.translate(20, 20)
+        |> Script.draw_rectangle(30, 30, :fill)
+        |> Script.pop_state()
+      end)
+
+    {width, _height, frame} =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        pixel_at(data, w, 10, 10) != {0, 0, 0}
+      end)
+
+    {r, g, b} = pixel_at(frame, width, 10, 10)
+    assert r in 120..135 and g in 120..135 and b in 120..135
+    # The overlap is no brighter than either rectangle alone.
+    assert pixel_at(frame, width, 25, 25) == {r, g, b}
+    assert pixel_at(frame, width, 45, 45) == {r, g, b}
+  end
+
+  test "blend_mode multiplies and clears until the state is popped" do
+    renderer =
+      start_script_scene(fn script ->
+        script
+        |> Script.fill_color(:yellow)
+        |> Script.draw_rectangle(60, 40, :fill)
+        |> Script.push_state()
+        |> op({:blend_mode, :multiply})
+        |> Script.fill_color(:magenta)
+        |> Script.draw_rectangle(30, 40, :fill)
+        |> Script.pop_state()
+        |> Script.push_state()
+        |> op({:blend_mode, :clear})
+        |> Script.translate(40, 10)
+        |> Script.draw_rectangle(10, 10, :fill)
+        |> Script.pop_state()
+        |> Script.draw_rectangle(10, 10, :fill)
+      end)
+
+    {width, _height, frame} =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        pixel_at(data, w, 50, 30) == {255, 255, 0}
+      end)
+
+    # Magenta multiplied over yellow leaves red, except where the last rect is drawn
+    # with the restored SrcOver mode.
+    assert pixel_at(frame, width, 20, 20) == {255, 0, 0}
+    assert pixel_at(frame, width, 5, 5) == {255, 255, 0}
+    # The cleared square shows the background.
+    assert pixel_at(frame, width, 45, 15) == {0, 0, 0}
+    assert pixel_at(frame, width, 50, 30) == {255, 255, 0}
+  end
+
+  test "drop_shadow and blur apply until the state is popped" do
+    renderer =
+      start_script_scene(fn script ->
+        script
+        |> Script.fill_color(:white)
+        |> Script.push_state()
+        |> op({:drop_shadow, {10, 10}, 0, :red})
+        |> Script.translate(4, 4)
+        |> Script.draw_rectangle(20, 20, :fill)
+        |> Script.pop_state()
+        |> Script.push_state()
+        |> op({:blur, 3})
+        |> Script.translate(4, 40)
+        |> Script.draw_rectangle(20, 20, :fill)
+        |> Script.pop_state()
+        |> Script.translate(40, 4)
+        |> Script.draw_rectangle(10, 10, :fill)
+      end)
+
+    {width, _height, frame} =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        pixel_at(data, w, 45, 9) == {255, 255, 255}
+      end)
+
+    assert pixel_at(frame, width, 14, 14) == {255, 255, 255}
+    # The shadow shows where it's offset past the rectangle.
+    assert red_pixel?(pixel_at(frame, width, 30, 30))
+    # The blurred rectangle fades out across its edge.
+    {r, _g, _b} = pixel_at(frame, width, 4, 50)
+    assert r in 40..215
+    assert elem(pixel_at(frame, width, 14, 50), 0) > 200
+    # Effects end with the state that set them.
+    refute red_pixel?(pixel_at(frame, width, 55, 19))
+  end
+
+  test "fill_gradient repeats linear stops and sweeps around a center" do
+    sweep_stops = [{0, :red}, {0.5, :red}, {0.5, :blue}, {1, :blue}]
+
+    renderer =
+      start_script_scene(fn script ->
+        script
+        |> op(
+          {:fill_gradient, {:linear, {0, 0}, {16, 0}}, [{0, :red}, {1, :blue}], tile: :repeat}
+        )
+        |> Script.draw_rectangle(64, 32, :fill)
+        |> op({:fill_gradient, {:sweep, {32, 48}}, sweep_stops})
+        |> Script.translate(0, 32)
+        |> Script.draw_rectangle(64, 32, :fill)
+      end)
+
+    {width, _height, frame} =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        red_pixel?(pixel_at(data, w, 32, 60))
+      end)
+
+    assert red_pixel?(pixel_at(frame, width, 1, 8))
+    assert blue_dominant?(pixel_at(frame, width, 14, 8))
+    # Past the end point the repeating gradient starts over.
+    assert red_pixel?(pixel_at(frame, width, 17, 8))
+    # The sweep runs clockwise from the positive x axis: red below the center, blue above.
+    assert blue_dominant?(pixel_at(frame, width, 32, 36))
+  end
+
   test "draw_script recursion guard prevents infinite loop" do
     assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
 
@@ -770,6 +1001,77 @@ defmodule Scenic.Driver.Skia.RasterPrimitivesTest do
     assert red_pixel?(pixel_at(frame, width, 15, 15))
   end
 
+  test "replacing a referenced script redraws its parent" do
+    assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
+
+    vp = ViewPortHelper.start(size: {64, 64}, scene: NestedScriptScene)
+    renderer = ViewPortHelper.renderer(vp)
+
+    on_exit(fn ->
+      if Process.alive?(vp.pid) do
+        _ = ViewPort.stop(vp)
+      end
+
+      _ = Native.stop(renderer)
+    end)
+
+    _ =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        red_pixel?(pixel_at(data, w, 15, 15))
+      end)
+
+    script =
+      Scenic.Script.start()
+      |> Scenic.Script.fill_color(:blue)
+      |> Scenic.Script.draw_rectangle(20, 20, :fill)
+      |> Scenic.Script.finish()
+
+    _ = ViewPort.put_script(vp, "child", script)
+
+    {width, _height, frame} =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        pixel_at(data, w, 15, 15) == {0, 0, 255}
+      end)
+
+    assert pixel_at(frame, width, 15, 15) == {0, 0, 255}
+  end
+
+  test "partial redraw keeps unchanged scripts on screen" do
+    assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
+
+    vp = ViewPortHelper.start(size: {64, 32}, scene: SideBySideScriptScene)
+    renderer = ViewPortHelper.renderer(vp)
+
+    on_exit(fn ->
+      if Process.alive?(vp.pid) do
+        _ = ViewPort.stop(vp)
+      end
+
+      _ = Native.stop(renderer)
+    end)
+
+    _ =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        red_pixel?(pixel_at(data, w, 10, 10)) and pixel_at(data, w, 50, 10) == {0, 255, 0}
+      end)
+
+    script =
+      Scenic.Script.start()
+      |> Scenic.Script.fill_color(:blue)
+      |> Scenic.Script.draw_rectangle(20, 20, :fill)
+      |> Scenic.Script.finish()
+
+    _ = ViewPort.put_script(vp, "left", script)
+
+    {width, _height, frame} =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        pixel_at(data, w, 10, 10) == {0, 0, 255}
+      end)
+
+    assert pixel_at(frame, width, 50, 10) == {0, 255, 0}
+    assert pixel_at(frame, width, 30, 10) == {0, 0, 0}
+  end
+
   test "draw_rect stroke only renders expected pixels" do
     assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
 
@@ -1699,6 +2001,121 @@ defmodule Scenic.Driver.Skia.RasterPrimitivesTest do
     assert red_pixel?(pixel_at(frame, width, 15, 20))
   end
 
+  test "cache_stats reports loaded images and del_static_image removes them" do
+    assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
+
+    vp = ViewPortHelper.start(size: {64, 64}, scene: StrokeImageScene)
+    renderer = ViewPortHelper.renderer(vp)
+
+    on_exit(fn ->
+      if Process.alive?(vp.pid) do
+        _ = ViewPort.stop(vp)
+      end
+
+      _ = Native.stop(renderer)
+    end)
+
+    _ =
+      wait_for_frame!(renderer, 40, fn {w, _h, data} ->
+        red_pixel?(pixel_at(data, w, 15, 20))
+      end)
+
+    assert {:ok, %{images: 1, image_bytes: bytes, budget_bytes: nil}} =
+             Scenic.Driver.Skia.cache_stats(renderer)
+
+    assert bytes > 0
+    assert :ok = Scenic.Driver.Skia.del_static_image(renderer, :test_red)
+    assert {:ok, %{images: 0, image_bytes: 0}} = Scenic.Driver.Skia.cache_stats(renderer)
+  end
+
+  test "set_font_fallback uploads the fallback fonts" do
+    assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
+
+    vp = ViewPortHelper.start(size: {64, 64}, scene: RectScene)
+    renderer = ViewPortHelper.renderer(vp)
+
+    on_exit(fn ->
+      if Process.alive?(vp.pid) do
+        _ = ViewPort.stop(vp)
+      end
+
+      _ = Native.stop(renderer)
+    end)
+
+    assert {:ok, %{fonts: fonts}} = Scenic.Driver.Skia.cache_stats(renderer)
+    assert :ok = Scenic.Driver.Skia.set_font_fallback(renderer, [:roboto_mono])
+    assert {:ok, %{fonts: loaded}} = Scenic.Driver.Skia.cache_stats(renderer)
+    assert loaded == fonts + 1
+
+    assert {:error, {:invalid_font, :test_red}} =
+             Scenic.Driver.Skia.set_font_fallback(renderer, [:test_red])
+  end
+
+  test "measure_text reports advance, bounds and line widths" do
+    assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
+
+    vp = ViewPortHelper.start(size: {64, 64}, scene: RectScene)
+    renderer = ViewPortHelper.renderer(vp)
+
+    on_exit(fn ->
+      if Process.alive?(vp.pid) do
+        _ = ViewPort.stop(vp)
+      end
+
+      _ = Native.stop(renderer)
+    end)
+
+    assert {:ok, metrics} = Scenic.Driver.Skia.measure_text(renderer, :roboto, 20, "Hello\nab")
+    assert %{width: width, line_widths: [first, second], ascent: ascent, descent: descent} =
+             metrics
+
+    assert width == first
+    assert second < first
+    assert ascent < 0 and descent > 0
+
+    {left, top, right, bottom} = metrics.bounds
+    assert left >= 0 and right <= width + 1
+    assert top < 0 and bottom > descent
+
+    assert {:error, {:invalid_font, :test_red}} =
+             Scenic.Driver.Skia.measure_text(renderer, :test_red, 20, "Hello")
+  end
+
+  test "font_variant ids load distinct faces and instances" do
+    assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
+
+    vp = ViewPortHelper.start(size: {64, 64}, scene: RectScene)
+    renderer = ViewPortHelper.renderer(vp)
+
+    on_exit(fn ->
+      if Process.alive?(vp.pid) do
+        _ = ViewPort.stop(vp)
+      end
+
+      _ = Native.stop(renderer)
+    end)
+
+    {:ok, hash} = Scenic.Assets.Static.to_hash(:roboto)
+    assert {:ok, ^hash} = Scenic.Driver.Skia.font_variant(:roboto)
+
+    assert {:ok, bold} = Scenic.Driver.Skia.font_variant(:roboto, axes: [wght: 700])
+    assert bold == hash <> "@wght=700.0"
+    assert {:ok, second_face} = Scenic.Driver.Skia.font_variant(:roboto, face: 1)
+    assert second_face == hash <> "#1"
+
+    assert {:ok, %{fonts: fonts}} = Scenic.Driver.Skia.cache_stats(renderer)
+    assert {:ok, _} = Scenic.Driver.Skia.measure_text(renderer, hash, 20, "Hello")
+    assert {:ok, _} = Scenic.Driver.Skia.measure_text(renderer, bold, 20, "Hello")
+    assert {:ok, %{fonts: loaded}} = Scenic.Driver.Skia.cache_stats(renderer)
+    assert loaded == fonts + 2
+
+    # roboto.ttf holds a single face.
+    assert {:error, {:invalid_font, ^second_face}} =
+             Scenic.Driver.Skia.measure_text(renderer, second_face, 20, "Hello")
+
+    assert {:error, {:invalid_font, :test_red}} = Scenic.Driver.Skia.font_variant(:test_red)
+  end
+
   test "stream fill renders provided bitmap" do
     assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
 
@@ -1823,6 +2240,23 @@ defmodule Scenic.Driver.Skia.RasterPrimitivesTest do
     assert square_max_x >= butt_max_x
   end
 
+  defp start_script_scene(build) do
+    assert {:ok, _} = Application.ensure_all_started(:scenic_driver_skia)
+
+    vp = ViewPortHelper.start(size: {64, 64}, scene: {ScriptScene, build})
+    renderer = ViewPortHelper.renderer(vp)
+
+    on_exit(fn ->
+      if Process.alive?(vp.pid) do
+        _ = ViewPort.stop(vp)
+      end
+
+      _ = Native.stop(renderer)
+    end)
+
+    renderer
+  end
+
   defp wait_for_frame!(renderer, attempts_remaining, predicate) do
     case Native.get_raster_frame(renderer) do
       {:ok, {width, height, frame}} = ok ->
@@ -1874,6 +2308,12 @@ defmodule Scenic.Driver.Skia.RasterPrimitivesTest do
     end)
   end
 
+  defp any_red?(frame, width, x_range, y_range) do
+    Enum.any?(x_range, fn x ->
+      Enum.any?(y_range, fn y -> red_pixel?(pixel_at(frame, width, x, y)) end)
+    end)
+  end
+
   defp red_pixel?({r, g, b}) do
     r > 200 and g < 80 and b < 80
   end
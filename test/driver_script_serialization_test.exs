@@ -3,6 +3,7 @@ defmodule Scenic.Driver.Skia.DriverScriptSerializationTest do
 
   import Scenic.Primitives
 
+  alias Scenic.Driver.Skia.Extension
   alias Scenic.Graph
   alias Scenic.Graph.Compiler
   alias Scenic.Script
@@ -49,4 +50,84 @@ defmodule Scenic.Driver.Skia.DriverScriptSerializationTest do
 
     assert binary == expected
   end
+
+  test "extension ops carry a version and a padded, length-prefixed payload" do
+    assert Extension.encode(0xABC, 2, <<1, 2, 3, 4, 5>>) ==
+             <<0xFA, 0xBC, 0x00, 0x02, 0x00, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5, 0, 0, 0>>
+
+    assert Extension.encode(0x001, 1, []) == <<0xF0, 0x01, 0x00, 0x01, 0::32>>
+
+    assert Extension.put([], 0x123, 1, "ab") == [{:skia_extension, 0x123, 1, "ab"}]
+    assert {:ok, <<0xF1, 0x23, _::binary>>} =
+             Extension.serialize({:skia_extension, 0x123, 1, "ab"})
+  end
+
+  test "built-in extensions encode their payloads" do
+    assert {:ok, <<0xF0, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0, 0>>} =
+             Extension.serialize({:text_draw_mode, :fill_stroke})
+
+    assert {:ok, <<0xF0, 0x02, 0x00, 0x01, 2::32, 0x00, 0x03, 0, 0>>} =
+             Extension.serialize({:text_decoration, [:underline, :strikethrough]})
+
+    assert {:ok, <<0xF0, 0x03, 0x00, 0x01, 4::32, 1.5::float-32-big>>} =
+             Extension.serialize({:letter_spacing, 1.5})
+
+    assert {:ok,
+            <<0xF0, 0x04, 0x00, 0x01, 12::32, 0x01::16, 0x01::16, 2::16, 0::16,
+              100.0::float-32-big>>} =
+             Extension.serialize({:text_wrap, 100, overflow: :ellipsis, max_lines: 2})
+
+    assert {:ok, <<0xF0, 0x05, 0x00, 0x01, 8::32, 0.5::float-32-big, 0::32>>} =
+             Extension.serialize({:push_layer, 0.5})
+
+    assert {:ok,
+            <<0xF0, 0x05, 0x00, 0x01, 24::32, 1.0::float-32-big, 0x01::16, 0::16,
+              1.0::float-32-big, 2.0::float-32-big, 30.0::float-32-big, 40.0::float-32-big>>} =
+             Extension.serialize({:push_layer, 1, bounds: {1, 2, 30, 40}})
+
+    assert {:ok, <<0xF0, 0x06, 0x00, 0x01, 2::32, 0x00, 0x18, 0, 0>>} =
+             Extension.serialize({:blend_mode, :multiply})
+
+    assert length(Extension.blend_modes()) == 29
+
+    assert {:ok,
+            <<0xF0, 0x07, 0x00, 0x01, 20::32, 2.0::float-32-big, 3.0::float-32-big,
+              4.0::float-32-big, 4.0::float-32-big, 0, 0, 0, 128>>} =
+             Extension.serialize({:drop_shadow, {2, 3}, 4, {:black, 128}})
+
+    assert {:ok, <<0xF0, 0x08, 0x00, 0x01, 8::32, 5.0::float-32-big, 1.0::float-32-big>>} =
+             Extension.serialize({:blur, {5, 1}})
+
+    assert {:ok, <<0xF0, 0x09, 0x00, 0x01, 0::32>>} = Extension.serialize({:backdrop_blur, nil})
+
+    assert {:ok,
+            <<0xF0, 0x0A, 0x00, 0x01, 48::32, 0x03::16, 0x00::16, 0x00::16, 2::16,
+              10.0::float-32-big, 20.0::float-32-big, 0.0::float-32-big, 360.0::float-32-big,
+              0::64, 0.0::float-32-big, 255, 0, 0, 255, 1.0::float-32-big, 0, 0, 255,
+              255>>} =
+             Extension.serialize({:fill_gradient, {:sweep, {10, 20}}, [{0, :red}, {1, :blue}]})
+
+    assert {:ok,
+            <<0xF0, 0x0B, 0x00, 0x01, 72::32, 0x00::16, 0x01::16, 0x01::16, 2::16,
+              _geometry::binary-size(24), 2.0::float-32-big, 0.0::float-32-big,
+              0.0::float-32-big, 2.0::float-32-big, 5.0::float-32-big, 6.0::float-32-big,
+              _stops::binary-size(16)>>} =
+             Extension.serialize(
+               {:stroke_gradient, {:linear, {0, 0}, {10, 0}}, [{0, :white}, {1, :black}],
+                tile: :repeat, matrix: {2, 0, 0, 2, 5, 6}}
+             )
+
+    assert Extension.serialize({:clip_path, :intersect}) == :error
+
+    assert_raise ArgumentError, fn -> Extension.serialize({:text_draw_mode, :outline}) end
+    assert_raise ArgumentError, fn -> Extension.serialize({:push_layer, 2}) end
+    assert_raise ArgumentError, fn -> Extension.serialize({:blend_mode, :burn}) end
+    assert_raise ArgumentError, fn -> Extension.serialize({:blur, -1}) end
+
+    unordered_stops = [{0.5, :red}, {0.2, :blue}]
+
+    assert_raise ArgumentError, fn ->
+      Extension.serialize({:fill_gradient, {:linear, {0, 0}, {1, 0}}, unordered_stops})
+    end
+  end
 end
@@ -0,0 +1,29 @@
+defmodule Scenic.Driver.Skia.TestSupport.ScriptScene do
+  @moduledoc false
+  # Draws one script built by the test. Start it with
+  # `ViewPortHelper.start(scene: {ScriptScene, build})`, where `build` receives the
+  # ops of `Scenic.Script.start/0` and returns them with the test's ops added.
+
+  use Scenic.Scene
+  import Scenic.Primitives
+  alias Scenic.Script
+
+  @script_id "script_scene"
+
+  def init(scene, build, _opts) when is_function(build, 1) do
+    graph =
+      Scenic.Graph.build()
+      |> script(@script_id)
+
+    script =
+      Script.start()
+      |> build.()
+      |> Script.finish()
+
+    scene = Scenic.Scene.push_script(scene, script, @script_id)
+    {:ok, Scenic.Scene.push_graph(scene, graph)}
+  end
+
+  # Adds a driver op, such as `{:push_layer, 0.5}`, to a script being built.
+  def op(ops, op), do: [op | ops]
+end
@@ -5,3 +5,4 @@ Logger.configure(level: :debug)
 {:ok, _apps} = Application.ensure_all_started(:scenic)
 
 Code.require_file("support/view_port_helper.exs", __DIR__)
+Code.require_file("support/script_scene.exs", __DIR__)